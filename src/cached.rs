@@ -1,19 +1,41 @@
-use std::{path::{PathBuf, Path}, fs, fmt::LowerHex};
+use std::{path::{PathBuf, Path}, fs, collections::{BTreeMap, HashMap, HashSet}, sync::Mutex, time::{SystemTime, UNIX_EPOCH}};
+use std::io::Write;
 
 use colored::Colorize;
+use fs2::FileExt;
 use futures::Future;
 use lazy_static::lazy_static;
 use meowhash::{MeowHasher, MeowHash};
-use tempfile::{NamedTempFile, tempdir, TempDir};
+use once_cell::sync::Lazy;
+use serde::{Serialize, Deserialize};
+use sha2::{Sha512, Digest};
+use tempfile::{tempdir, TempDir};
+
+use crate::config::{CONFIG, CacheBackend};
+
+static UPSTREAM_CLIENT: Lazy<reqwest::Client> = Lazy::new(reqwest::Client::new);
 
 lazy_static! {
     static ref TMP_DIR: TempDir = tempdir().expect("Failed to create temp directory for cache emulation");
+    static ref INDEX_LOCK: Mutex<()> = Mutex::new(());
 }
 
 const CONTENTS_DIR: &str = "contents";
 const URL_DIR: &str = "by_url_hash";
+const INDEX_FILE: &str = "index.cbor";
+const QUARANTINE_DIR: &str = "quarantine";
+const LOCK_FILE: &str = "lock";
+// in-progress writes; never matched as contents or URL links
+const TEMP_PREFIX: &str = ".tmp.";
+/// A cache hit only rewrites the index once the entry's `last_used` is this
+/// many seconds out of date, so pruning by age is only this precise.
+const LAST_USED_RESOLUTION: u64 = 60 * 60;
 
 pub fn cache_dir() -> PathBuf {
+    if let Some(dir) = &CONFIG.cache.dir {
+        return dir.clone();
+    }
+
     dirs::cache_dir().map_or_else(
         || TMP_DIR.path().to_path_buf(),
         |v| v.join("jet-cache")
@@ -21,29 +43,150 @@ pub fn cache_dir() -> PathBuf {
 }
 
 pub fn needs_cache_emulation() -> bool {
-    dirs::cache_dir().is_none()
+    CONFIG.cache.dir.is_none() && dirs::cache_dir().is_none()
 }
 
 fn cached_url_as_name(hash: &MeowHash) -> String {
     format!("u.{:016x}.dat", hash.as_u128())
 }
 
-fn cached_contents_as_name(hash: &MeowHash) -> String {
-    format!("f.{:016x}.dat", hash.as_u128())
+fn cached_contents_as_name(hash: u128) -> String {
+    format!("f.{:016x}.dat", hash)
 }
 
-pub fn cached_url_exists(url: &str) -> bool {
-    fs::symlink_metadata(cache_dir()
-        .join(URL_DIR)
-        .join(cached_url_as_name(&MeowHasher::hash(url.as_bytes()))))
-        .is_ok_and(|f| f.is_symlink())
+pub fn contents_path(hash: u128) -> PathBuf {
+    cache_dir()
+        .join(CONTENTS_DIR)
+        .join(cached_contents_as_name(hash))
 }
 
-fn url_real_path(url: &str) -> Result<PathBuf, std::io::Error> {
-    cache_dir()
-        .join(URL_DIR)
-        .join(cached_url_as_name(&MeowHasher::hash(url.as_bytes())))
-        .canonicalize()
+/// Writes `bytes` to a temporary file next to `path`, syncs it and renames it
+/// into place, so readers only ever see complete files.
+fn write_atomic(path: &Path, bytes: &[u8]) -> std::io::Result<()> {
+    let parent = path.parent().expect("Cache paths always have a parent");
+    fs::create_dir_all(parent)?;
+
+    let mut file = tempfile::Builder::new().prefix(TEMP_PREFIX).tempfile_in(parent)?;
+    file.write_all(bytes)?;
+
+    // temporary files are private, but hardlinks to cached contents end up
+    // in expanded targets, so give them the permissions of a normal file
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        file.as_file().set_permissions(fs::Permissions::from_mode(0o644))?;
+    }
+
+    file.as_file().sync_all()?;
+    file.persist(path).map_err(|err| err.error)?;
+    Ok(())
+}
+
+/// Takes the cache-wide lock shared by every jet process using this cache
+/// directory. It is released when the returned file is dropped.
+fn lock_cache() -> std::io::Result<fs::File> {
+    fs::create_dir_all(cache_dir())?;
+    let file = fs::OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(cache_dir().join(LOCK_FILE))?;
+    file.lock_exclusive()?;
+    Ok(file)
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs())
+}
+
+/// Everything jet knows about a single cached URL. Timestamps are seconds
+/// since the UNIX epoch.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct IndexEntry {
+    pub contents: u128,
+    pub size: usize,
+    pub fetched: u64,
+    pub last_used: u64,
+    /// SHA-512 an archive expected this URL to have, once the contents matched it.
+    #[serde(default)]
+    pub sha512: Option<String>,
+    /// ETag the origin sent with the contents, for revalidation.
+    #[serde(default)]
+    pub etag: Option<String>
+}
+
+/// The cache index, stored as CBOR next to the cached data. Entries are keyed
+/// by their original URL, which cannot be recovered from the file names alone.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct CacheIndex {
+    pub entries: BTreeMap<String, IndexEntry>,
+    /// Hex SHA-512 digests of cached contents, so identical files served from
+    /// different URLs only need to be downloaded once.
+    #[serde(default)]
+    pub by_sha512: BTreeMap<String, u128>,
+    /// Set once creating a symlink in this cache directory has failed.
+    #[serde(default)]
+    pub no_symlinks: bool
+}
+
+impl CacheIndex {
+    fn path() -> PathBuf {
+        cache_dir().join(INDEX_FILE)
+    }
+
+    /// When the index was last written, to tell whether a loaded copy is stale.
+    pub fn modified() -> Option<SystemTime> {
+        fs::metadata(Self::path()).and_then(|m| m.modified()).ok()
+    }
+
+    pub fn load() -> Self {
+        let bytes = match fs::read(Self::path()) {
+            Ok(bytes) => bytes,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Self::default(),
+            Err(err) => {
+                eprintln!("{}: failed to read cache index: {}", "warning".yellow(), err);
+                return Self::default();
+            }
+        };
+
+        ciborium::from_reader(&bytes[..]).unwrap_or_else(|err| {
+            eprintln!("{}: cache index is corrupt and will be rebuilt: {}", "warning".yellow(), err);
+            Self::default()
+        })
+    }
+
+    fn save(&self) -> Result<(), Box<dyn std::error::Error>> {
+        let mut bytes = Vec::new();
+        ciborium::into_writer(self, &mut bytes)?;
+        write_atomic(&Self::path(), &bytes)?;
+        Ok(())
+    }
+
+    /// Loads the index, applies `f` and writes the result back while holding
+    /// the cache lock, so concurrent downloads in this or any other jet process
+    /// never lose each other's updates.
+    pub fn update<R, F: FnOnce(&mut Self) -> R>(f: F) -> R {
+        let _guard = INDEX_LOCK.lock().unwrap_or_else(|err| err.into_inner());
+        let _lock = lock_cache().map_err(|err| {
+            eprintln!("{}: failed to lock the cache; concurrent jet processes may conflict: {}", "warning".yellow(), err);
+        });
+
+        let mut index = Self::load();
+        let result = f(&mut index);
+
+        if let Err(err) = index.save() {
+            eprintln!("{}: failed to save cache index: {}", "warning".yellow(), err);
+        }
+
+        result
+    }
+
+    /// [`CacheIndex::update`] for async code. Taking the lock can wait on
+    /// other jet processes, so it happens on the blocking thread pool.
+    pub async fn update_async<R : Send + 'static, F : FnOnce(&mut Self) -> R + Send + 'static>(f: F) -> R {
+        tokio::task::spawn_blocking(move || Self::update(f)).await
+            .unwrap_or_else(|err| panic!("Failed to update cache index: {err}"))
+    }
 }
 
 pub enum CacheState {
@@ -51,89 +194,693 @@ pub enum CacheState {
     Miss { bytes_downloaded: usize, hash: u128 }
 }
 
+fn sha512_matches(bytes: &[u8], expected: &[u8; 64]) -> bool {
+    Sha512::digest(bytes)[..] == expected[..]
+}
+
+/// How long a cached response stays valid.
+#[derive(Clone, Copy, Debug)]
+pub enum CachePolicy {
+    /// The URL always serves the same contents (pinned versions, hashed files).
+    Immutable,
+    /// Revalidated once the cached copy is older than this many seconds.
+    Ttl(u64),
+    /// Revalidated with the origin on every use.
+    Revalidate
+}
+
+/// What the origin returned to a (possibly conditional) request.
+pub enum Fetched {
+    Data { bytes: Vec<u8>, etag: Option<String> },
+    /// The origin answered `304 Not Modified` to the ETag it was given.
+    NotModified
+}
+
+/// Fetches `url` from the cache, or with `download` if it is not cached yet.
+/// When `sha512` is known, a cached copy that does not match it is ignored and
+/// a matching copy has the digest recorded in the index for `jet cache verify`.
 pub async fn download<
     Fu: Future<Output = Result<Vec<u8>, Box<dyn std::error::Error>>>,
     F: FnOnce() -> Fu
->(url: &str, download: F) -> Result<(CacheState, Vec<u8>), Box<dyn std::error::Error>> {
+>(url: &str, sha512: Option<&[u8; 64]>, download: F) -> Result<(CacheState, Vec<u8>), Box<dyn std::error::Error>> {
+    download_with_policy(url, CachePolicy::Immutable, sha512, |_| async {
+        Ok(Fetched::Data { bytes: download().await?, etag: None })
+    }).await
+}
+
+/// Like [`download`], for URLs whose contents may change. `download` is given
+/// the ETag of the cached copy, if there is one, to make a conditional request.
+pub async fn download_with_policy<
+    Fu: Future<Output = Result<Fetched, Box<dyn std::error::Error>>>,
+    F: FnOnce(Option<String>) -> Fu
+>(url: &str, policy: CachePolicy, sha512: Option<&[u8; 64]>, download: F) -> Result<(CacheState, Vec<u8>), Box<dyn std::error::Error>> {
     let url_path = cache_dir()
         .join(URL_DIR)
         .join(cached_url_as_name(&MeowHasher::hash(url.as_bytes())));
     
-    if std::fs::symlink_metadata(&url_path).is_ok_and(|f| f.is_symlink()) {
-        match url_path.canonicalize() {
-            Ok(canon) => match tokio::fs::read(&canon).await {
-                Ok(bytes) => {
-                    let hash = MeowHasher::hash(&bytes[..]);
-                    let contents_path = cache_dir()
-                    .join(CONTENTS_DIR)
-                    .join(cached_contents_as_name(&hash));
-
-                    match contents_path.canonicalize() {
-                        Ok(contents_path) => if canon == contents_path {
-                            return Ok((CacheState::Hit { hash: hash.as_u128() }, bytes))
-                        } else {
-                            eprintln!("{}: file path {:?} does not match expected path {:?}", "warning".yellow(), &canon, &contents_path);
-                            fs::remove_file(&url_path)?;
-                        },
-                        Err(err) => {
-                            eprintln!("{}: error canonicalizing expected path {:?}: {}", "warning".yellow(), &contents_path, err);
-                            fs::remove_file(&url_path)?;
-                        },
-                    };
-                },
-                Err(_) => {
-                    eprintln!("{}: failed to read canon file {:?}", "warning".yellow(), &canon);
-                    fs::remove_file(&url_path)?;
-                    if fs::metadata(&canon).is_ok() {
-                        fs::remove_file(&canon)?;
-                    }
-                },
-            },
-            Err(err) => {
-                eprintln!("{}: failed to canonicalize existing URL symlink {:?}: {}", "warning".yellow(), &url_path, err);
-            },
-        };
+    let index = CacheIndex::load();
+    let cached = if uses_symlinks(&index) {
+        lookup_symlink(&url_path).await
+    } else {
+        lookup_index(url).await
+    };
+    let (fetched, etag) = index.entries.get(url)
+        .map_or((0, None), |entry| (entry.fetched, entry.etag.clone()));
+
+    let cached = cached.filter(|(_, bytes)| {
+        let matches = sha512.is_none_or(|sha512| sha512_matches(bytes, sha512));
+        if !matches {
+            eprintln!("{}: cached contents of {} do not match the expected SHA-512; downloading again", "warning".yellow(), url);
+        }
+
+        matches
+    });
+
+    let fresh = match policy {
+        CachePolicy::Immutable => true,
+        CachePolicy::Ttl(secs) => fetched.saturating_add(secs) > now(),
+        CachePolicy::Revalidate => false
+    };
+
+    let mut download = Some(download);
+    let mut revalidated = false;
+
+    if let (Some(_), false) = (&cached, fresh) {
+        let download = download.take().unwrap();
+        let response = download(etag.clone()).await?;
+        match response {
+            Fetched::NotModified => revalidated = true,
+            Fetched::Data { bytes, etag } => return store(url, &url_path, sha512, bytes, etag).await
+        }
+    }
+
+    if let Some((hash, bytes)) = cached {
+        let digest = sha512.map(hex::encode);
+        let recorded = index.entries.get(url).is_some_and(|entry| !revalidated && entry.contents == hash
+            && now().saturating_sub(entry.last_used) < LAST_USED_RESOLUTION
+            && digest.as_ref().is_none_or(|digest| entry.sha512.as_ref() == Some(digest) && index.by_sha512.contains_key(digest)));
+
+        if !recorded {
+            let url = url.to_string();
+            let size = bytes.len();
+            CacheIndex::update_async(move |index| record_hit(index, url, hash, size, revalidated, digest)).await;
+        }
+
+        return Ok((CacheState::Hit { hash }, bytes));
     }
     
-    let bytes = download().await?;
+    if let Some(sha512) = sha512 {
+        if let Some((hash, bytes)) = find_by_sha512(sha512).await {
+            let url = url.to_string();
+            let url_path = url_path.clone();
+            let digest = hex::encode(sha512);
+            let size = bytes.len();
+
+            CacheIndex::update_async(move |index| {
+                if link_url(index, &url, &url_path, &contents_path(hash)) {
+                    let now = now();
+                    index.entries.insert(url, IndexEntry {
+                        contents: hash,
+                        size,
+                        fetched: now,
+                        last_used: now,
+                        sha512: Some(digest),
+                        etag: None
+                    });
+                }
+            }).await;
+
+            return Ok((CacheState::Hit { hash }, bytes));
+        }
+    }
+
+    // an upstream cache could hand back a stale copy of anything mutable
+    if let CachePolicy::Immutable = policy {
+        if let Some(bytes) = fetch_upstream(url, sha512).await {
+            return store(url, &url_path, sha512, bytes, None).await;
+        }
+    }
+
+    let download = download.take().expect("Download was already used for revalidation");
+    let response = download(None).await?;
+    match response {
+        Fetched::Data { bytes, etag } => store(url, &url_path, sha512, bytes, etag).await,
+        Fetched::NotModified => Err(format!("{} answered Not Modified to an unconditional request", url).into())
+    }
+}
+
+/// Records a cache hit on `url` in the index.
+fn record_hit(index: &mut CacheIndex, url: String, hash: u128, size: usize, revalidated: bool, digest: Option<String>) {
+    let fetched = fs::metadata(contents_path(hash))
+        .and_then(|m| m.modified())
+        .ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map_or_else(now, |d| d.as_secs());
+
+    let entry = index.entries.entry(url).or_insert(IndexEntry {
+        contents: hash,
+        size,
+        fetched,
+        last_used: 0,
+        sha512: None,
+        etag: None
+    });
+
+    entry.last_used = now();
+    if revalidated {
+        entry.fetched = now();
+    }
+
+    if let Some(digest) = digest {
+        entry.sha512 = Some(digest.clone());
+        index.by_sha512.insert(digest, hash);
+    }
+}
+
+/// Saves freshly downloaded contents of `url` into the cache, unless they do
+/// not match `sha512`.
+async fn store(url: &str, url_path: &Path, sha512: Option<&[u8; 64]>, bytes: Vec<u8>, etag: Option<String>) -> Result<(CacheState, Vec<u8>), Box<dyn std::error::Error>> {
     let byte_len = bytes.len();
     let hash = MeowHasher::hash(&bytes[..]);
+    let digest = hex::encode(Sha512::digest(&bytes));
 
-    let contents_path = cache_dir()
-            .join(CONTENTS_DIR)
-            .join(cached_contents_as_name(&hash));
-    
-    for path in [&contents_path, &url_path] {
-        if let Some(parent) = path.parent() {
-            fs::create_dir_all(parent)?;
-        }
+    if let Some(sha512) = sha512.map(hex::encode).filter(|sha512| *sha512 != digest) {
+        return Err(format!("{} failed SHA-512 check (downloaded: {}, expected: {})", url, digest, sha512).into());
     }
+
+    let contents_path = contents_path(hash.as_u128());
+    
+    let written = {
+        let contents_path = contents_path.clone();
+        let bytes = bytes.clone();
+        tokio::task::spawn_blocking(move || write_atomic(&contents_path, &bytes)).await?
+    };
     
-    if let Err(err) = tokio::fs::write(&contents_path, &bytes).await {
+    if let Err(err) = written {
         eprintln!("{}: failed to save cache data to {:?}: {:?}; future cachable requests will miss URL {}", "warning".yellow(), &contents_path, err, url);
     } else {
-        if let Ok(existing) = fs::symlink_metadata(&url_path) {
-            if existing.is_file() || existing.is_symlink() {
-                tokio::fs::remove_file(&url_path).await?;
-            } else if existing.is_dir() {
-                tokio::fs::remove_dir_all(&url_path).await?;
+        let url = url.to_string();
+        let url_path = url_path.to_path_buf();
+        let sha512 = sha512.map(hex::encode);
+
+        CacheIndex::update_async(move |index| {
+            if link_url(index, &url, &url_path, &contents_path) {
+                let now = now();
+                index.entries.insert(url, IndexEntry {
+                    contents: hash.as_u128(),
+                    size: byte_len,
+                    fetched: now,
+                    last_used: now,
+                    sha512,
+                    etag
+                });
+                index.by_sha512.insert(digest, hash.as_u128());
+            }
+        }).await;
+    }
+    
+    Ok((CacheState::Miss { bytes_downloaded: byte_len, hash: hash.as_u128() }, bytes))
+}
+
+/// Asks the upstream cache for `url`, by SHA-512 first when it is known, then
+/// by URL, which makes the upstream download and keep it if it has to.
+/// Anything not matching `sha512` is ignored, so the origin is used instead.
+async fn fetch_upstream(url: &str, sha512: Option<&[u8; 64]>) -> Option<Vec<u8>> {
+    let upstream = CONFIG.cache.upstream.as_ref()?.trim_end_matches('/');
+
+    let mut requests = vec![];
+    if let Some(sha512) = sha512 {
+        requests.push(format!("{}/sha512/{}", upstream, hex::encode(sha512)));
+    }
+    requests.push(reqwest::Url::parse_with_params(&format!("{}/url", upstream), [("u", url)])
+        .map(String::from)
+        .ok()?);
+
+    for request in requests {
+        match UPSTREAM_CLIENT.get(&request).send().await {
+            Ok(response) if response.status() == reqwest::StatusCode::OK => match response.bytes().await {
+                Ok(bytes) if sha512.is_some_and(|sha512| Sha512::digest(&bytes)[..] != sha512[..]) => {
+                    eprintln!("{}: upstream cache {} sent a copy of {} with the wrong SHA-512; ignoring it", "warning".yellow(), upstream, url);
+                },
+                Ok(bytes) => {
+                    println!("{:>12} {} (from {})", "Upstream".blue(), url, upstream);
+                    return Some(bytes.into());
+                },
+                Err(err) => eprintln!("{}: failed to read {} from upstream cache: {}", "warning".yellow(), url, err)
+            },
+            Ok(_) => {},
+            Err(err) => {
+                eprintln!("{}: upstream cache {} is unreachable; using origin: {}", "warning".yellow(), upstream, err);
+                return None;
             }
         }
+    }
+
+    None
+}
 
-        if let Err(err) = symlink::symlink_file(&contents_path, &url_path) {
-            eprintln!("{}: failed to create cache symlink to {:?} in {:?}: {:?}; future cachable requests will miss URL {}", "warning".yellow(), &contents_path, &url_path, err, url);
+/// Whether URL mappings are kept as symlinks in `by_url_hash/` rather than
+/// only in the index.
+pub fn uses_symlinks(index: &CacheIndex) -> bool {
+    match CONFIG.cache.backend {
+        CacheBackend::Auto => !index.no_symlinks,
+        CacheBackend::Symlink => true,
+        CacheBackend::Index => false
+    }
+}
+
+/// Reads the contents a URL symlink points at. Anything unexpected is treated
+/// as a miss rather than cleaned up, as another jet process may be relinking
+/// this URL right now; the download replaces the link atomically and
+/// `jet cache verify` removes leftovers.
+async fn lookup_symlink(url_path: &Path) -> Option<(u128, Vec<u8>)> {
+    if !fs::symlink_metadata(url_path).is_ok_and(|f| f.is_symlink()) {
+        return None;
+    }
+
+    let canon = url_path.canonicalize()
+        .inspect_err(|err| eprintln!("{}: failed to canonicalize existing URL symlink {:?}: {}", "warning".yellow(), url_path, err))
+        .ok()?;
+    let bytes = tokio::fs::read(&canon).await
+        .inspect_err(|err| eprintln!("{}: failed to read canon file {:?}: {}", "warning".yellow(), &canon, err))
+        .ok()?;
+    let hash = MeowHasher::hash(&bytes[..]).as_u128();
+    let contents_path = contents_path(hash);
+    let contents_path = contents_path.canonicalize()
+        .inspect_err(|err| eprintln!("{}: error canonicalizing expected path {:?}: {}", "warning".yellow(), &contents_path, err))
+        .ok()?;
+
+    if canon != contents_path {
+        eprintln!("{}: file path {:?} does not match expected path {:?}", "warning".yellow(), &canon, &contents_path);
+        return None;
+    }
+
+    Some((hash, bytes))
+}
+
+/// Reads the contents the index maps a URL to, for caches without symlinks.
+async fn lookup_index(url: &str) -> Option<(u128, Vec<u8>)> {
+    let hash = CacheIndex::load().entries.get(url)?.contents;
+    let bytes = tokio::fs::read(contents_path(hash)).await.ok()?;
+
+    if MeowHasher::hash(&bytes[..]).as_u128() != hash {
+        eprintln!("{}: cached contents {:016x} of {} do not match their name", "warning".yellow(), hash, url);
+        return None;
+    }
+
+    Some((hash, bytes))
+}
+
+/// Points the link for `url` at `contents_path`. The new link is created
+/// under a temporary name and renamed over the old one, so readers see either
+/// link but never neither. Without symlinks the index entry the caller adds is
+/// the only mapping; if symlinks turn out to be unsupported, the cache switches
+/// to that mode for good. Must be called from [`CacheIndex::update`].
+fn link_url(index: &mut CacheIndex, url: &str, url_path: &Path, contents_path: &Path) -> bool {
+    if !uses_symlinks(index) {
+        return true;
+    }
+
+    let result = (|| {
+        let parent = url_path.parent().expect("Cache paths always have a parent");
+        fs::create_dir_all(parent)?;
+
+        if fs::symlink_metadata(url_path).is_ok_and(|existing| existing.is_dir()) {
+            fs::remove_dir_all(url_path)?;
         }
+
+        let temp_path = parent.join(format!("{}{}", TEMP_PREFIX, hex::encode(rand::random::<[u8; 8]>())));
+        symlink::symlink_file(contents_path, &temp_path)?;
+        fs::rename(&temp_path, url_path).inspect_err(|_| {
+            let _ = fs::remove_file(&temp_path);
+        })
+    })();
+
+    let Err(err) = result else {
+        return true;
+    };
+
+    if CONFIG.cache.backend == CacheBackend::Auto {
+        eprintln!("{}: failed to create cache symlink ({}); keeping URL mappings in the cache index from now on", "note".blue(), err);
+        index.no_symlinks = true;
+        return true;
     }
-    
-    Ok((CacheState::Miss { bytes_downloaded: byte_len, hash: hash.as_u128() }, bytes))
+
+    eprintln!("{}: failed to create cache symlink to {:?} in {:?}: {:?}; future cachable requests will miss URL {}", "warning".yellow(), contents_path, url_path, err, url);
+    false
 }
 
-pub async fn download_and_save<
-    P: AsRef<Path>,
-    Fu: Future<Output = Result<Vec<u8>, Box<dyn std::error::Error>>>,
-    F: FnOnce() -> Fu
->(file_path: P, url: &str, download: F) -> Result<CacheState, Box<dyn std::error::Error>> {
-    let (cache_state, bytes) = self::download(url, download).await?;
-    tokio::fs::write(file_path, bytes).await?;
-    Ok(cache_state)
+/// Looks for contents previously downloaded from any URL with this SHA-512.
+async fn find_by_sha512(sha512: &[u8; 64]) -> Option<(u128, Vec<u8>)> {
+    let hash = *CacheIndex::load().by_sha512.get(&hex::encode(sha512))?;
+    let bytes = tokio::fs::read(contents_path(hash)).await.ok()?;
+
+    if MeowHasher::hash(&bytes[..]).as_u128() != hash || !sha512_matches(&bytes, sha512) {
+        eprintln!("{}: cached contents {:016x} do not match their recorded SHA-512; ignoring", "warning".yellow(), hash);
+        return None;
+    }
+
+    Some((hash, bytes))
+}
+
+/// Parses a size such as `512M`, `10G` or `1.5GiB` into bytes.
+pub fn parse_size(value: &str) -> Result<u64, String> {
+    let value = value.trim();
+    let split = value.find(|c: char| !(c.is_ascii_digit() || c == '.')).unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+    let number: f64 = number.parse().map_err(|_| format!("invalid size: {}", value))?;
+
+    let multiplier: u64 = match unit.trim().to_ascii_lowercase().as_str() {
+        "" | "b" => 1,
+        "k" | "kb" | "kib" => 1 << 10,
+        "m" | "mb" | "mib" => 1 << 20,
+        "g" | "gb" | "gib" => 1 << 30,
+        "t" | "tb" | "tib" => 1 << 40,
+        other => return Err(format!("unknown size unit: {}", other))
+    };
+
+    Ok((number * multiplier as f64) as u64)
+}
+
+/// Parses a duration such as `45m`, `12h`, `30d` or `2w` into seconds.
+pub fn parse_duration(value: &str) -> Result<u64, String> {
+    let value = value.trim();
+    let split = value.find(|c: char| !c.is_ascii_digit()).unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+    let number: u64 = number.parse().map_err(|_| format!("invalid duration: {}", value))?;
+
+    let multiplier = match unit.trim() {
+        "" | "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        other => return Err(format!("unknown duration unit: {}", other))
+    };
+
+    Ok(number * multiplier)
+}
+
+#[derive(Default)]
+pub struct PruneOptions {
+    pub max_size: Option<u64>,
+    /// Seconds since last use after which contents are evicted.
+    pub older_than: Option<u64>,
+    /// URLs whose contents must never be evicted.
+    pub keep_urls: HashSet<String>
+}
+
+#[derive(Default)]
+pub struct PruneReport {
+    pub files_removed: usize,
+    pub bytes_freed: u64,
+    pub bytes_kept: u64,
+    pub urls_removed: usize
+}
+
+fn parse_contents_name(name: &str) -> Option<u128> {
+    let hex = name.strip_prefix("f.")?.strip_suffix(".dat")?;
+    u128::from_str_radix(hex, 16).ok()
+}
+
+/// When each cached contents was last used through any URL.
+fn last_used_by_contents(index: &CacheIndex) -> HashMap<u128, u64> {
+    let mut last_used = HashMap::<u128, u64>::new();
+
+    for entry in index.entries.values() {
+        let used = last_used.entry(entry.contents).or_insert(0);
+        *used = (*used).max(entry.last_used);
+    }
+
+    last_used
+}
+
+/// Evicts least recently used contents until the cache satisfies `options`,
+/// removing the URL links and index entries that pointed at them.
+pub fn prune(options: &PruneOptions) -> PruneReport {
+    let mut report = PruneReport::default();
+
+    // what to evict is picked from a snapshot, so the lock is only held while
+    // removing it
+    let snapshot = CacheIndex::load();
+    let Ok(dir) = fs::read_dir(cache_dir().join(CONTENTS_DIR)) else {
+        return report;
+    };
+
+    let last_used = last_used_by_contents(&snapshot);
+    let kept: HashSet<u128> = snapshot.entries.iter()
+        .filter(|(url, _)| options.keep_urls.contains(*url))
+        .map(|(_, entry)| entry.contents)
+        .collect();
+
+    // contents cached before the index existed fall back to their mtime
+    let mut contents: Vec<(u128, PathBuf, u64, u64)> = dir
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let hash = parse_contents_name(entry.file_name().to_str()?)?;
+            let metadata = entry.metadata().ok()?;
+            let used = last_used.get(&hash).copied().unwrap_or_else(|| metadata.modified().ok()
+                .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+                .map_or(0, |d| d.as_secs()));
+
+            Some((hash, entry.path(), metadata.len(), used))
+        })
+        .collect();
+    contents.sort_by_key(|(_, _, _, used)| *used);
+
+    let mut total: u64 = contents.iter().map(|(_, _, size, _)| size).sum();
+    let cutoff = options.older_than.map(|secs| now().saturating_sub(secs));
+    let mut evict = vec![];
+
+    for (hash, path, size, used) in contents {
+        let too_old = cutoff.is_some_and(|cutoff| used < cutoff);
+        let too_big = options.max_size.is_some_and(|max| total > max);
+
+        if kept.contains(&hash) || !(too_old || too_big) {
+            continue;
+        }
+
+        total -= size;
+        evict.push((hash, path, size, used));
+    }
+
+    report.bytes_kept = total;
+
+    CacheIndex::update(|index| {
+        let last_used = last_used_by_contents(index);
+        let mut removed = HashSet::<u128>::new();
+
+        for (hash, path, size, used) in evict {
+            // another jet process used it since the snapshot
+            if last_used.get(&hash).is_some_and(|last_used| *last_used > used) {
+                report.bytes_kept += size;
+                continue;
+            }
+
+            if let Err(err) = fs::remove_file(&path) {
+                eprintln!("{}: failed to remove {:?}: {}", "warning".yellow(), &path, err);
+                report.bytes_kept += size;
+                continue;
+            }
+
+            report.files_removed += 1;
+            report.bytes_freed += size;
+            removed.insert(hash);
+        }
+
+        index.by_sha512.retain(|_, hash| !removed.contains(hash));
+
+        index.entries.retain(|url, entry| {
+            if !removed.contains(&entry.contents) {
+                return true;
+            }
+
+            let url_path = cache_dir()
+                .join(URL_DIR)
+                .join(cached_url_as_name(&MeowHasher::hash(url.as_bytes())));
+
+            if let Err(err) = fs::remove_file(&url_path) {
+                if err.kind() != std::io::ErrorKind::NotFound {
+                    eprintln!("{}: failed to remove {:?}: {}", "warning".yellow(), &url_path, err);
+                }
+            }
+
+            report.urls_removed += 1;
+            false
+        });
+
+        // links to contents that are gone (including ones the index never knew)
+        if let Ok(dir) = fs::read_dir(cache_dir().join(URL_DIR)) {
+            for entry in dir.filter_map(|entry| entry.ok()) {
+                let path = entry.path();
+                if fs::symlink_metadata(&path).is_ok_and(|m| m.is_symlink()) && fs::metadata(&path).is_err()
+                    && fs::remove_file(&path).is_ok() {
+                    report.urls_removed += 1;
+                }
+            }
+        }
+    });
+
+    report
+}
+
+#[derive(Default)]
+pub struct VerifyReport {
+    pub files_checked: usize,
+    pub files_broken: usize,
+    pub links_removed: usize,
+    pub entries_removed: usize
+}
+
+/// SHA-512 digests recorded for each cached contents, with where they were
+/// recorded.
+fn expected_sha512s(index: &CacheIndex) -> HashMap<u128, Vec<(String, String)>> {
+    let mut expected = HashMap::<u128, Vec<(String, String)>>::new();
+    for (url, entry) in &index.entries {
+        if let Some(sha512) = &entry.sha512 {
+            expected.entry(entry.contents).or_default().push((url.clone(), sha512.clone()));
+        }
+    }
+
+    for (sha512, hash) in &index.by_sha512 {
+        expected.entry(*hash).or_default().push(("content lookups".to_string(), sha512.clone()));
+    }
+
+    expected
+}
+
+/// What is wrong with the contents at `path`, if anything. Contents that are
+/// gone (pruned by another process meanwhile) are not a problem.
+fn check_contents(path: &Path, hash: u128, expected: &HashMap<u128, Vec<(String, String)>>) -> Option<String> {
+    match fs::read(path) {
+        Ok(bytes) => if MeowHasher::hash(&bytes[..]).as_u128() != hash {
+            Some("contents do not match file name".to_string())
+        } else {
+            let digest = hex::encode(Sha512::digest(&bytes));
+            expected.get(&hash).and_then(|urls| urls.iter()
+                .find(|(_, sha512)| *sha512 != digest)
+                .map(|(url, _)| format!("SHA-512 does not match the one recorded for {}", url)))
+        },
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => None,
+        Err(err) => Some(format!("unreadable: {}", err))
+    }
+}
+
+/// Re-hashes everything in `contents/`, removing (or moving to `quarantine/`)
+/// files that no longer match their name or the SHA-512 recorded for them, and
+/// drops URL links and index entries that no longer lead anywhere.
+pub fn verify(quarantine: bool) -> VerifyReport {
+    let mut report = VerifyReport::default();
+
+    // rehashing everything takes a while, so it happens without the lock and
+    // only broken files are checked again while holding it
+    let expected = expected_sha512s(&CacheIndex::load());
+    let mut suspects = vec![];
+
+    if let Ok(dir) = fs::read_dir(cache_dir().join(CONTENTS_DIR)) {
+        for entry in dir.filter_map(|entry| entry.ok()) {
+            let Some(hash) = entry.file_name().to_str().and_then(parse_contents_name) else {
+                continue;
+            };
+
+            report.files_checked += 1;
+            if check_contents(&entry.path(), hash, &expected).is_some() {
+                suspects.push((hash, entry.file_name(), entry.path()));
+            }
+        }
+    }
+
+    CacheIndex::update(|index| {
+        let expected = expected_sha512s(index);
+        let mut broken = HashSet::<u128>::new();
+
+        for (hash, file_name, path) in suspects {
+            // a download may have replaced it since
+            let Some(problem) = check_contents(&path, hash, &expected) else {
+                continue;
+            };
+
+            println!("{:>12} {} ({})", "Broken".red(), path.to_str().unwrap(), problem);
+            report.files_broken += 1;
+            broken.insert(hash);
+
+            let result = if quarantine {
+                let target = cache_dir().join(QUARANTINE_DIR).join(file_name);
+                fs::create_dir_all(cache_dir().join(QUARANTINE_DIR))
+                    .and_then(|_| fs::rename(&path, &target))
+            } else {
+                fs::remove_file(&path)
+            };
+
+            if let Err(err) = result {
+                eprintln!("{}: failed to remove {:?}: {}", "warning".yellow(), &path, err);
+            }
+        }
+
+        let before = index.entries.len();
+        let uses_symlinks = uses_symlinks(index);
+        index.entries.retain(|url, entry| {
+            let url_path = cache_dir()
+                .join(URL_DIR)
+                .join(cached_url_as_name(&MeowHasher::hash(url.as_bytes())));
+
+            !broken.contains(&entry.contents) && contents_path(entry.contents).exists()
+                && (!uses_symlinks || fs::symlink_metadata(url_path).is_ok())
+        });
+        report.entries_removed = before - index.entries.len();
+        index.by_sha512.retain(|_, hash| !broken.contains(hash) && contents_path(*hash).exists());
+
+        if let Ok(dir) = fs::read_dir(cache_dir().join(URL_DIR)) {
+            for entry in dir.filter_map(|entry| entry.ok()) {
+                let path = entry.path();
+                if fs::metadata(&path).is_err() && fs::remove_file(&path).is_ok() {
+                    println!("{:>12} {} (dangling)", "Unlink".yellow(), path.to_str().unwrap());
+                    report.links_removed += 1;
+                }
+            }
+        }
+    });
+
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sizes() {
+        assert_eq!(parse_size("512"), Ok(512));
+        assert_eq!(parse_size("512B"), Ok(512));
+        assert_eq!(parse_size("10k"), Ok(10 << 10));
+        assert_eq!(parse_size("512M"), Ok(512 << 20));
+        assert_eq!(parse_size("1.5GiB"), Ok(3 << 29));
+        assert_eq!(parse_size(" 2 TB "), Ok(2 << 40));
+    }
+
+    #[test]
+    fn invalid_sizes() {
+        assert!(parse_size("").is_err());
+        assert!(parse_size("G").is_err());
+        assert!(parse_size("10X").is_err());
+        assert!(parse_size("1.2.3M").is_err());
+    }
+
+    #[test]
+    fn durations() {
+        assert_eq!(parse_duration("90"), Ok(90));
+        assert_eq!(parse_duration("90s"), Ok(90));
+        assert_eq!(parse_duration("45m"), Ok(45 * 60));
+        assert_eq!(parse_duration("12h"), Ok(12 * 60 * 60));
+        assert_eq!(parse_duration("30d"), Ok(30 * 24 * 60 * 60));
+        assert_eq!(parse_duration("2w"), Ok(2 * 7 * 24 * 60 * 60));
+    }
+
+    #[test]
+    fn invalid_durations() {
+        assert!(parse_duration("").is_err());
+        assert!(parse_duration("d").is_err());
+        assert!(parse_duration("1.5h").is_err());
+        assert!(parse_duration("3y").is_err());
+    }
 }
@@ -0,0 +1,146 @@
+// importing curseforge modpack zips
+//
+// a modpack zip has a manifest.json listing the project and file IDs of its
+// mods, plus an overrides directory copied over the result
+
+use std::path::Path;
+
+use colored::Colorize;
+use futures::future::join_all;
+use serde::Deserialize;
+
+use crate::{config::CONFIG, curseforge, import::{self, Element, Jetfuel}, jp::{self, ProjectInfo}};
+
+const MANIFEST_NAME: &str = "manifest.json";
+const DEFAULT_OVERRIDES: &str = "overrides";
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct Manifest {
+    minecraft: Minecraft,
+    manifest_type: String,
+    manifest_version: u32,
+    name: String,
+    version: Option<String>,
+    author: Option<String>,
+    files: Vec<ManifestFile>,
+    overrides: Option<String>
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct Minecraft {
+    version: String,
+    #[serde(default)]
+    mod_loaders: Vec<ModLoader>
+}
+
+#[derive(Deserialize, Debug)]
+struct ModLoader {
+    /// The loader and its version, e.g. `fabric-0.14.21`.
+    id: String,
+    #[serde(default)]
+    primary: bool
+}
+
+#[derive(Deserialize, Debug)]
+struct ManifestFile {
+    #[serde(rename = "projectID")]
+    project_id: u32,
+    #[serde(rename = "fileID")]
+    file_id: u32,
+    /// Files are required unless this says otherwise.
+    required: Option<bool>
+}
+
+/// The directory files of a project in class `class_id` go in.
+fn class_dir(class_id: Option<u32>) -> &'static str {
+    match class_id {
+        Some(12) => "resourcepacks",
+        Some(6552) => "shaderpacks",
+        _ => "mods"
+    }
+}
+
+/// Turns the CurseForge modpack zip at `file` into a jetfuel.xml, and the
+/// files it embeds, in `output_dir`.
+pub async fn import<P1 : AsRef<Path>, P2 : AsRef<Path>>(file: P1, output_dir: P2) {
+    let file = file.as_ref();
+    let output_dir = output_dir.as_ref();
+
+    let mut archive = import::open_zip(file);
+    let manifest: Manifest = import::read_zip_json(&mut archive, file, MANIFEST_NAME);
+
+    if manifest.manifest_type != "minecraftModpack" || manifest.manifest_version != 1 {
+        panic!("Unsupported modpack: {} version {}", manifest.manifest_type, manifest.manifest_version);
+    }
+
+    import::prepare_output(output_dir);
+
+    let client = jp::http_client();
+    let mut jetfuel = Jetfuel::new(ProjectInfo {
+        name: manifest.name.clone(),
+        description: String::new(),
+        version: manifest.version.clone().unwrap_or_default(),
+        authors: manifest.author.clone().into_iter().collect()
+    });
+
+    let loaders = &manifest.minecraft.mod_loaders;
+    let loader = loaders.iter().find(|loader| loader.primary).or(loaders.first());
+
+    match loader.map(|loader| loader.id.split_once('-').unwrap_or((&loader.id, ""))) {
+        Some(("fabric", loader_version)) => {
+            let loader_version = loader_version.to_string();
+            if let Some(server) = import::fabric_server(&client, Some(&manifest.minecraft.version), Some(&loader_version)).await {
+                jetfuel.add(Path::new(""), server);
+            }
+        },
+        Some((loader, _)) => import::warn_unsupported_loader(loader),
+        None => {}
+    }
+
+    let files: Vec<_> = manifest.files.iter()
+        .filter(|file| {
+            if file.required == Some(false) {
+                println!("{:>12} file {} of project {} (optional)", "Skipped".yellow(), file.file_id, file.project_id);
+                return false;
+            }
+
+            true
+        })
+        .collect();
+
+    // without an API key, nothing says which files are not mods
+    let places: Vec<_> = if CONFIG.curseforge.api_key.is_some() {
+        join_all(files.iter().map(|file| {
+            let client = &client;
+
+            async move {
+                let project = curseforge::mod_get(client, &file.project_id.to_string()).await;
+                (class_dir(project.class_id), project.slug)
+            }
+        })).await
+    } else {
+        eprintln!("{}: no CurseForge API key is set, so every file is put in mods/; set curseforge.api-key in the config file to sort out resource packs and shaders",
+            "warning".yellow());
+        files.iter().map(|file| ("mods", file.project_id.to_string())).collect()
+    };
+
+    for (file, (dir, project)) in files.iter().zip(places) {
+        jetfuel.add(Path::new(dir), Element::new("curseforge")
+            .attribute("project", project)
+            .attribute("file", file.file_id.to_string()));
+    }
+
+    let overrides = format!("{}/", manifest.overrides.as_deref().unwrap_or(DEFAULT_OVERRIDES).trim_end_matches('/'));
+    let overrides = import::extract_overrides(&mut archive, &[&overrides], output_dir, &mut jetfuel);
+
+    jetfuel.write(output_dir);
+
+    println!("{:>12} {}: {} CurseForge file(s), {} embedded file(s) into {}",
+        "Imported".green(),
+        manifest.name,
+        files.len(),
+        overrides,
+        output_dir.to_str().unwrap());
+}
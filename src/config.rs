@@ -0,0 +1,183 @@
+// global jet configuration, read from the user's config directory
+//
+// settings are merged from (lowest to highest priority) the config file,
+// environment variables and command line options
+
+use std::{path::PathBuf, fs, env};
+
+use clap::ValueEnum;
+use once_cell::sync::{Lazy, OnceCell};
+use serde::{Deserialize, Serialize};
+
+use crate::cached;
+
+pub static CONFIG: Lazy<Config> = Lazy::new(Config::load);
+static OVERRIDES: OnceCell<Overrides> = OnceCell::new();
+
+#[derive(ValueEnum, Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Compression {
+    None,
+    Zlib
+}
+
+/// A size in bytes, written like `512M` or `10G`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(try_from = "String", into = "String")]
+pub struct Size {
+    text: String,
+    pub bytes: u64
+}
+
+impl TryFrom<String> for Size {
+    type Error = String;
+
+    fn try_from(text: String) -> Result<Self, Self::Error> {
+        Ok(Self { bytes: cached::parse_size(&text)?, text })
+    }
+}
+
+impl From<Size> for String {
+    fn from(size: Size) -> Self {
+        size.text
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum CacheBackend {
+    /// Symlinks, until creating one fails.
+    #[default] Auto,
+    Symlink,
+    /// URL mappings live only in the cache index, for filesystems without symlinks.
+    Index
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(rename_all = "kebab-case")]
+#[serde(deny_unknown_fields)]
+pub struct CacheConfig {
+    /// Where the cache lives, instead of `jet-cache` in the user's cache directory.
+    pub dir: Option<PathBuf>,
+    /// Prune the cache down to this size (e.g. `10G`) after every pack or expand.
+    pub max_size: Option<Size>,
+    #[serde(default)]
+    pub backend: CacheBackend,
+    /// A `jet cache serve` instance to ask before downloading from the origin.
+    pub upstream: Option<String>
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Mirror {
+    pub from: String,
+    pub to: String
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(rename_all = "kebab-case")]
+#[serde(deny_unknown_fields)]
+pub struct DownloadConfig {
+    /// Maximum number of simultaneous downloads during `jet expand`, at least 1; unlimited if unset.
+    pub concurrency: Option<usize>,
+    /// URL prefixes to download from somewhere else instead.
+    #[serde(default)]
+    pub mirrors: Vec<Mirror>
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(rename_all = "kebab-case")]
+#[serde(deny_unknown_fields)]
+pub struct GithubConfig {
+    /// Base URL of the GitHub REST API, `https://api.github.com` if unset.
+    pub api_url: Option<String>,
+    /// Token sent with API requests and release asset downloads, for private
+    /// repositories and higher rate limits.
+    pub token: Option<String>
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(rename_all = "kebab-case")]
+#[serde(deny_unknown_fields)]
+pub struct CurseforgeConfig {
+    /// Base URL of the CurseForge API, `https://api.curseforge.com` if unset.
+    pub api_url: Option<String>,
+    /// Required to pack `<curseforge>` entries.
+    pub api_key: Option<String>
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(rename_all = "kebab-case")]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// Compression used by `jet pack` when `--compression` is not given.
+    pub compression: Option<Compression>,
+    #[serde(default)]
+    pub cache: CacheConfig,
+    #[serde(default)]
+    pub download: DownloadConfig,
+    #[serde(default)]
+    pub github: GithubConfig,
+    #[serde(default)]
+    pub curseforge: CurseforgeConfig
+}
+
+/// Settings given on the command line, which win over everything else.
+#[derive(Default)]
+pub struct Overrides {
+    pub cache_dir: Option<PathBuf>,
+    pub cache_upstream: Option<String>
+}
+
+/// Must be called before [`CONFIG`] is first used to have any effect.
+pub fn set_overrides(overrides: Overrides) {
+    if OVERRIDES.set(overrides).is_err() {
+        panic!("Configuration overrides were set twice");
+    }
+}
+
+pub fn config_path() -> Option<PathBuf> {
+    env::var_os("JET_CONFIG").map(PathBuf::from)
+        .or_else(|| dirs::config_dir().map(|dir| dir.join("jet").join("config.toml")))
+}
+
+impl Config {
+    fn load() -> Self {
+        let mut config = match config_path() {
+            Some(path) => match fs::read_to_string(&path) {
+                Ok(contents) => toml::from_str(&contents)
+                    .unwrap_or_else(|err| panic!("Failed to read config file {:?}: {err}", path)),
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => Self::default(),
+                Err(err) => panic!("Failed to open config file {:?}: {err}", path)
+            },
+            None => Self::default()
+        };
+
+        if config.download.concurrency == Some(0) {
+            panic!("Invalid config: download.concurrency must be at least 1");
+        }
+
+        if let Some(dir) = env::var_os("JET_CACHE_DIR") {
+            config.cache.dir = Some(dir.into());
+        }
+
+        if let Some(overrides) = OVERRIDES.get() {
+            if let Some(dir) = &overrides.cache_dir {
+                config.cache.dir = Some(dir.clone());
+            }
+
+            if let Some(upstream) = &overrides.cache_upstream {
+                config.cache.upstream = Some(upstream.clone());
+            }
+        }
+
+        config
+    }
+
+    /// Rewrites `url` to the first mirror whose `from` prefix it starts with.
+    pub fn mirrored(&self, url: &str) -> String {
+        self.download.mirrors.iter()
+            .find_map(|mirror| url.strip_prefix(&mirror.from[..]).map(|rest| format!("{}{}", mirror.to, rest)))
+            .unwrap_or_else(|| url.to_string())
+    }
+}
@@ -0,0 +1,139 @@
+// simple and small curseforge api stuff
+
+use colored::Colorize;
+use reqwest::{StatusCode, Url};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+
+use crate::{cached::{CacheState, CachePolicy, Fetched}, config::CONFIG};
+
+const DEFAULT_API_URL: &str = "https://api.curseforge.com";
+const MINECRAFT_GAME_ID: u32 = 432;
+const MODS_CLASS_ID: u32 = 6;
+
+/// Files never change once uploaded, but whether their authors allow other
+/// programs to download them can, so responses are re-checked daily.
+const RESPONSE_TTL: u64 = 24 * 60 * 60;
+
+#[derive(Deserialize, Serialize, Debug)]
+struct DataResponse<T> {
+    data: T
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ModLinks {
+    pub website_url: Option<String>
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Mod {
+    pub id: u32,
+    pub slug: String,
+    pub name: String,
+    pub links: ModLinks,
+    /// What kind of project this is, e.g. 6 for mods.
+    pub class_id: Option<u32>,
+    /// `false` when the authors do not allow other programs to download files.
+    pub allow_mod_distribution: Option<bool>
+}
+
+#[derive(Deserialize, Serialize, Debug, PartialEq, Eq)]
+pub struct FileHash {
+    pub value: String,
+    /// 1 is SHA-1, 2 is MD5
+    pub algo: u32
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct File {
+    pub id: u32,
+    pub file_name: String,
+    pub file_length: usize,
+    /// Missing when the author does not allow third-party downloads.
+    pub download_url: Option<String>,
+    pub hashes: Vec<FileHash>
+}
+
+impl File {
+    pub fn sha1(&self) -> Option<&str> {
+        self.hashes.iter().find(|hash| hash.algo == 1).map(|hash| &hash.value[..])
+    }
+}
+
+fn api_url() -> &'static str {
+    CONFIG.curseforge.api_url.as_deref().unwrap_or(DEFAULT_API_URL).trim_end_matches('/')
+}
+
+async fn api_get<T : DeserializeOwned + Serialize>(client: &reqwest::Client, path: &str, what: &str) -> T {
+    let Some(api_key) = &CONFIG.curseforge.api_key else {
+        panic!("CurseForge needs an API key to get {}; set curseforge.api-key in the config file", what);
+    };
+
+    let url = format!("{}{}", api_url(), path);
+
+    let (cache_state, bytes) = crate::cached::download_with_policy(&url.clone()[..], CachePolicy::Ttl(RESPONSE_TTL), None, move |_| async move {
+        let response = client.get(url)
+            .header("x-api-key", api_key)
+            .send().await
+            .unwrap_or_else(|err| panic!("Failed to GET {}: {err}", what));
+
+        match response.status() {
+            StatusCode::OK => {
+                let bytes: Vec<u8> = response.bytes().await.expect("Could not read bytes from CurseForge request").into();
+                let response = serde_json::from_slice::<DataResponse<T>>(&bytes[..])
+                    .unwrap_or_else(|err| panic!("Failed to deserialize {}: {err}", what));
+
+                let mut real_bytes = Vec::new();
+                ciborium::into_writer(&response.data, &mut real_bytes)
+                    .unwrap_or_else(|err| panic!("Failed to serialize {}: {err}", what));
+                Ok(Fetched::Data { bytes: real_bytes, etag: None })
+            },
+            StatusCode::NOT_FOUND => panic!("Unknown CurseForge {}", what),
+            StatusCode::FORBIDDEN => panic!("CurseForge refused the API key getting {}", what),
+            status => panic!("Random status code getting CurseForge {}: {:?}", what, status)
+        }
+    }).await.unwrap_or_else(|err| panic!("Failed to get CurseForge {}: {err}", what));
+
+    if let CacheState::Miss { bytes_downloaded, hash } = cache_state {
+        println!("{:>12} (downloaded {} bytes as {:016x})", "Cache Miss".magenta(), bytes_downloaded, hash);
+    }
+
+    ciborium::from_reader(&bytes[..])
+        .unwrap_or_else(|err| panic!("Failed to deserialize {}: {err}", what))
+}
+
+/// Gets a project by its numeric ID or its slug. Slugs are only unique within
+/// a class, so a mod is preferred over other projects with the same slug.
+pub async fn mod_get(client: &reqwest::Client, project: &str) -> Mod {
+    if project.chars().all(|c| c.is_ascii_digit()) {
+        return api_get(client, &format!("/v1/mods/{}", project), &format!("project {}", project)).await;
+    }
+
+    let mut search = Url::parse("http://localhost/v1/mods/search").unwrap();
+    search.query_pairs_mut()
+        .append_pair("gameId", &MINECRAFT_GAME_ID.to_string())
+        .append_pair("slug", project);
+
+    let found: Vec<Mod> = api_get(
+        client,
+        &format!("{}?{}", search.path(), search.query().unwrap_or_default()),
+        &format!("project {}", project)
+    ).await;
+
+    let mut found: Vec<_> = found.into_iter()
+        .filter(|found| found.slug == project)
+        .collect();
+
+    match found.iter().position(|found| found.class_id == Some(MODS_CLASS_ID)) {
+        Some(i) => found.swap_remove(i),
+        None if found.len() == 1 => found.remove(0),
+        None if found.is_empty() => panic!("Unknown CurseForge project {}", project),
+        None => panic!("More than one CurseForge project is called {}, and none of them is a mod; use its numeric ID", project)
+    }
+}
+
+pub async fn mod_file_get(client: &reqwest::Client, mod_id: u32, file_id: &str) -> File {
+    api_get(client, &format!("/v1/mods/{}/files/{}", mod_id, file_id), &format!("file {} of project {}", file_id, mod_id)).await
+}
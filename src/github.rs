@@ -0,0 +1,105 @@
+// simple and small github releases api stuff
+
+use colored::Colorize;
+use reqwest::{RequestBuilder, StatusCode, header::{ACCEPT, AUTHORIZATION}};
+use serde::Deserialize;
+
+use crate::{config::CONFIG, pattern};
+
+const DEFAULT_API_URL: &str = "https://api.github.com";
+
+#[derive(Deserialize, Debug)]
+pub struct ReleaseAsset {
+    pub name: String,
+    pub size: usize,
+    pub browser_download_url: String,
+    /// API URL of the asset, which serves its contents to
+    /// `Accept: application/octet-stream`.
+    pub url: String
+}
+
+impl ReleaseAsset {
+    /// Where to download the asset from. Only the API URL works for private
+    /// repositories, so it is used whenever a token is configured.
+    pub fn download_url(&self) -> &str {
+        match CONFIG.github.token {
+            Some(_) => &self.url,
+            None => &self.browser_download_url
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct Release {
+    pub tag_name: String,
+    pub assets: Vec<ReleaseAsset>
+}
+
+impl Release {
+    /// The only asset whose name matches `asset_pattern`.
+    pub fn asset(&self, repo: &str, asset_pattern: &str) -> &ReleaseAsset {
+        let matching: Vec<_> = self.assets.iter()
+            .filter(|asset| pattern::matches(asset_pattern, &asset.name))
+            .collect();
+
+        match &matching[..] {
+            [asset] => asset,
+            [] => panic!("No asset of {} {} matches {} (assets: {})", repo, self.tag_name, asset_pattern, self.asset_names()),
+            _ => panic!("More than one asset of {} {} matches {} (assets: {})", repo, self.tag_name, asset_pattern, self.asset_names())
+        }
+    }
+
+    fn asset_names(&self) -> String {
+        self.assets.iter().map(|asset| &asset.name[..]).collect::<Vec<_>>().join(", ")
+    }
+}
+
+fn api_url() -> &'static str {
+    CONFIG.github.api_url.as_deref().unwrap_or(DEFAULT_API_URL).trim_end_matches('/')
+}
+
+/// A GET request for `url`. Release assets downloaded through the GitHub API
+/// are asked for as raw contents, with the configured token.
+pub fn download_request(client: &reqwest::Client, url: &str) -> RequestBuilder {
+    let request = client.get(CONFIG.mirrored(url));
+    if !url.starts_with(&format!("{}/", api_url())) {
+        return request;
+    }
+
+    let request = request.header(ACCEPT, "application/octet-stream");
+    match &CONFIG.github.token {
+        Some(token) => request.header(AUTHORIZATION, format!("Bearer {}", token)),
+        None => request
+    }
+}
+
+/// Gets the release tagged `tag` in `repo` (`owner/name`). The tag `latest`
+/// means the most recent non-prerelease.
+pub async fn release_get(client: &reqwest::Client, repo: &str, tag: &str) -> Release {
+    let api_url = api_url();
+    let url = if tag == "latest" {
+        format!("{}/repos/{}/releases/latest", api_url, repo)
+    } else {
+        format!("{}/repos/{}/releases/tags/{}", api_url, repo, tag)
+    };
+
+    let mut request = client.get(&url).header(ACCEPT, "application/vnd.github+json");
+    if let Some(token) = &CONFIG.github.token {
+        request = request.header(AUTHORIZATION, format!("Bearer {}", token));
+    }
+
+    let response = request.send().await
+        .unwrap_or_else(|err| panic!("Failed to GET release {} of {}: {err}", tag, repo));
+
+    match response.status() {
+        StatusCode::OK => {
+            println!("{:>12} {} {} [release info]", "GET".magenta(), repo, tag);
+            let bytes = response.bytes().await
+                .unwrap_or_else(|err| panic!("Failed to read release {} of {}: {err}", tag, repo));
+            serde_json::from_slice(&bytes[..])
+                .unwrap_or_else(|err| panic!("Failed to deserialize release {} of {}: {err}", tag, repo))
+        },
+        StatusCode::NOT_FOUND => panic!("Unknown GitHub release {} of {}", tag, repo),
+        status => panic!("Random status code getting GitHub release {} of {}: {:?}", tag, repo, status)
+    }
+}
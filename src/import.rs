@@ -0,0 +1,284 @@
+// shared parts of importing packs from other formats
+//
+// jetfuel.xml is written out by hand, in the same layout as a hand-written
+// one, since it is meant to be edited afterwards
+
+use std::{collections::BTreeMap, fs::{self, File}, io::Read, path::{Component, Path, PathBuf}};
+
+use colored::Colorize;
+use quick_xml::escape::escape;
+use reqwest::StatusCode;
+use serde::{Deserialize, de::DeserializeOwned};
+use zip::ZipArchive;
+
+use crate::{config::CONFIG, jp::ProjectInfo, modrinth::{self, ProjectVersionGetResponse, VersionFile}};
+
+const INDENT: &str = "    ";
+
+pub struct Element {
+    tag: &'static str,
+    attributes: Vec<(&'static str, String)>,
+    children: Vec<Element>
+}
+
+impl Element {
+    pub fn new(tag: &'static str) -> Self {
+        Self { tag, attributes: vec![], children: vec![] }
+    }
+
+    pub fn attribute<S : Into<String>>(mut self, name: &'static str, value: S) -> Self {
+        self.attributes.push((name, value.into()));
+        self
+    }
+
+    fn name(&self) -> Option<&str> {
+        self.attributes.iter()
+            .find(|(name, _)| *name == "name")
+            .map(|(_, value)| &value[..])
+    }
+
+    fn write(&self, out: &mut String, depth: usize) {
+        out.push_str(&INDENT.repeat(depth));
+        out.push('<');
+        out.push_str(self.tag);
+
+        for (name, value) in &self.attributes {
+            out.push_str(&format!(" {}=\"{}\"", name, escape(value)));
+        }
+
+        if self.children.is_empty() {
+            out.push_str("/>\n");
+            return;
+        }
+
+        out.push_str(">\n");
+        for child in &self.children {
+            child.write(out, depth + 1);
+        }
+        out.push_str(&format!("{}</{}>\n", INDENT.repeat(depth), self.tag));
+    }
+}
+
+/// A jetfuel.xml being put together.
+pub struct Jetfuel {
+    project: ProjectInfo,
+    contents: Vec<Element>
+}
+
+impl Jetfuel {
+    pub fn new(project: ProjectInfo) -> Self {
+        Self { project, contents: vec![] }
+    }
+
+    /// Adds `element` inside the `<directory>` elements for `dir` (e.g.
+    /// `config/sub`), creating any that are missing.
+    pub fn add(&mut self, dir: &Path, element: Element) {
+        let mut contents = &mut self.contents;
+
+        for component in dir.components() {
+            let name = component.as_os_str().to_str().expect("Imported paths are UTF-8");
+            let position = match contents.iter().position(|child| child.tag == "directory" && child.name() == Some(name)) {
+                Some(position) => position,
+                None => {
+                    contents.push(Element::new("directory").attribute("name", name));
+                    contents.len() - 1
+                }
+            };
+
+            contents = &mut contents[position].children;
+        }
+
+        contents.push(element);
+    }
+
+    pub fn to_xml(&self) -> String {
+        let mut out = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\n<jetfuel>\n");
+
+        out.push_str(&format!("{INDENT}<project>\n"));
+        out.push_str(&format!("{INDENT}{INDENT}<name>{}</name>\n", escape(&self.project.name)));
+        out.push_str(&format!("{INDENT}{INDENT}<description>{}</description>\n", escape(&self.project.description)));
+        out.push_str(&format!("{INDENT}{INDENT}<version>{}</version>\n", escape(&self.project.version)));
+        for author in &self.project.authors {
+            out.push_str(&format!("{INDENT}{INDENT}<author>{}</author>\n", escape(author)));
+        }
+        out.push_str(&format!("{INDENT}</project>\n"));
+
+        for element in &self.contents {
+            out.push('\n');
+            element.write(&mut out, 1);
+        }
+
+        out.push_str("</jetfuel>\n");
+        out
+    }
+
+    /// Writes `jetfuel.xml` into `output_dir`.
+    pub fn write<P : AsRef<Path>>(&self, output_dir: P) {
+        let path = output_dir.as_ref().join("jetfuel.xml");
+        fs::write(&path, self.to_xml())
+            .unwrap_or_else(|err| panic!("Failed to write {:?}: {err}", path));
+    }
+}
+
+/// Creates `output_dir` for a new jetfuel.xml, refusing to replace an existing one.
+pub fn prepare_output<P : AsRef<Path>>(output_dir: P) {
+    let output_dir = output_dir.as_ref();
+    let jetfuel_path = output_dir.join("jetfuel.xml");
+
+    if jetfuel_path.exists() {
+        panic!("{:?} already exists; move it away or use another directory", jetfuel_path);
+    }
+
+    fs::create_dir_all(output_dir)
+        .unwrap_or_else(|err| panic!("Failed to create {:?}: {err}", output_dir));
+}
+
+/// Checks that `path` (from an imported pack) stays inside the directory it is
+/// relative to.
+pub fn relative_path(path: &str) -> PathBuf {
+    let mut relative = PathBuf::new();
+
+    for component in Path::new(path).components() {
+        match component {
+            Component::Normal(name) => relative.push(name),
+            Component::CurDir => {},
+            _ => panic!("Refusing to import {:?}, which points outside the pack", path)
+        }
+    }
+
+    if relative.as_os_str().is_empty() {
+        panic!("Refusing to import a file without a path");
+    }
+
+    relative
+}
+
+/// Writes an embedded file of the imported pack to `relative` in `output_dir`.
+pub fn write_file<P : AsRef<Path>>(output_dir: P, relative: &Path, data: &[u8]) {
+    let path = output_dir.as_ref().join(relative);
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .unwrap_or_else(|err| panic!("Failed to create {:?}: {err}", parent));
+    }
+
+    fs::write(&path, data)
+        .unwrap_or_else(|err| panic!("Failed to write {:?}: {err}", path));
+}
+
+pub fn open_zip(path: &Path) -> ZipArchive<File> {
+    let file = File::open(path)
+        .unwrap_or_else(|err| panic!("Failed to open {:?}: {err}", path));
+    ZipArchive::new(file)
+        .unwrap_or_else(|err| panic!("Failed to read {:?}: {err}", path))
+}
+
+/// Reads the JSON file `name` from the modpack at `path`.
+pub fn read_zip_json<T : DeserializeOwned>(archive: &mut ZipArchive<File>, path: &Path, name: &str) -> T {
+    let mut entry = archive.by_name(name)
+        .unwrap_or_else(|err| panic!("{:?} has no {}: {err}", path, name));
+
+    let mut bytes = vec![];
+    entry.read_to_end(&mut bytes)
+        .unwrap_or_else(|err| panic!("Failed to read {}: {err}", name));
+    serde_json::from_slice(&bytes[..])
+        .unwrap_or_else(|err| panic!("Failed to read {}: {err}", name))
+}
+
+/// Extracts the files under each of `prefixes` (e.g. `overrides/`) in a
+/// modpack into `output_dir`, and lists them in `jetfuel`. Files under later
+/// prefixes replace those under earlier ones. Returns how many there were.
+pub fn extract_overrides(archive: &mut ZipArchive<File>, prefixes: &[&str], output_dir: &Path, jetfuel: &mut Jetfuel) -> usize {
+    let names: Vec<_> = archive.file_names().map(String::from).collect();
+    let mut sources = BTreeMap::new();
+
+    for prefix in prefixes {
+        for name in &names {
+            if let Some(path) = name.strip_prefix(prefix).filter(|path| !path.is_empty() && !path.ends_with('/')) {
+                sources.insert(relative_path(path), name);
+            }
+        }
+    }
+
+    for (path, name) in &sources {
+        let mut entry = archive.by_name(name)
+            .unwrap_or_else(|err| panic!("Failed to read {} from the modpack: {err}", name));
+        let mut data = vec![];
+        entry.read_to_end(&mut data)
+            .unwrap_or_else(|err| panic!("Failed to read {} from the modpack: {err}", name));
+
+        write_file(output_dir, path, &data);
+
+        let file_name = path.file_name().and_then(|name| name.to_str()).expect("Imported paths are UTF-8");
+        jetfuel.add(path.parent().unwrap_or(Path::new("")), Element::new("file").attribute("name", file_name));
+    }
+
+    sources.len()
+}
+
+/// Whether a Modrinth `file` can be saved under `name` by a `<modrinth>`
+/// entry. Other names, or ones that read as patterns, need a `<download>`.
+pub fn can_keep_name(file: &VersionFile, name: &str) -> bool {
+    file.filename == name && !name.contains(['*', '?'])
+}
+
+/// A pinned `<modrinth>` entry for just `file` of `version`, keeping its name.
+pub fn modrinth_element(project: &str, version: &ProjectVersionGetResponse, file: &VersionFile) -> Element {
+    let element = Element::new("modrinth")
+        .attribute("project", project)
+        .attribute("version", &version.id);
+
+    if modrinth::primary_file(&version.files).is_some_and(|primary| std::ptr::eq(primary, file)) {
+        element.attribute("keep-filename", "true")
+    } else {
+        element.attribute("files", &file.filename)
+    }
+}
+
+pub fn warn_unsupported_loader(loader: &str) {
+    eprintln!("{}: the pack uses {}, but jet only sets up Fabric servers; add one yourself", "warning".yellow(), loader);
+}
+
+/// The `<fabric-server>` for a pack on `minecraft` with Fabric Loader
+/// `fabric_loader`, if it has both.
+pub async fn fabric_server(client: &reqwest::Client, minecraft: Option<&String>, fabric_loader: Option<&String>) -> Option<Element> {
+    let (Some(minecraft), Some(fabric_loader)) = (minecraft, fabric_loader) else {
+        return None;
+    };
+
+    Some(Element::new("fabric-server")
+        .attribute("minecraft", minecraft)
+        .attribute("loader", fabric_loader)
+        .attribute("installer", latest_fabric_installer(client).await))
+}
+
+#[derive(Deserialize)]
+struct FabricInstaller {
+    version: String,
+    stable: bool
+}
+
+/// The newest stable Fabric installer, for packs that only say which loader
+/// version they use.
+async fn latest_fabric_installer(client: &reqwest::Client) -> String {
+    let url = "https://meta.fabricmc.net/v2/versions/installer";
+    let response = client.get(CONFIG.mirrored(url)).send().await
+        .unwrap_or_else(|err| panic!("Failed to GET {}: {err}", url));
+
+    let installers: Vec<FabricInstaller> = match response.status() {
+        StatusCode::OK => {
+            let bytes = response.bytes().await
+                .unwrap_or_else(|err| panic!("Failed to read {}: {err}", url));
+            serde_json::from_slice(&bytes[..])
+                .unwrap_or_else(|err| panic!("Failed to read {}: {err}", url))
+        },
+        status => panic!("GET {} returned random status code {}", url, status)
+    };
+
+    let installer = installers.into_iter()
+        .find(|installer| installer.stable)
+        .unwrap_or_else(|| panic!("{} lists no stable installer", url));
+
+    println!("{:>12} fabric installer -> {}", "Resolved".green(), installer.version);
+    installer.version
+}
@@ -0,0 +1,226 @@
+// writing a jetfuel.xml for an existing server directory
+//
+// the jetfuel.xml is written into the server directory itself, so its
+// `<file>` entries point at the files already there. it is only a starting
+// point and should be read over before packing
+
+use std::{collections::{HashMap, HashSet}, fs::{self, File}, io::Read, path::{Path, PathBuf}};
+
+use colored::Colorize;
+use sha2::{Digest, Sha512};
+use zip::ZipArchive;
+
+use crate::{import::{self, Element, Jetfuel}, jp::{self, ProjectInfo}, jp_zlib, modrinth};
+
+/// Top-level directories the server creates for itself.
+const SKIPPED_DIRS: [&str; 5] = ["logs", "crash-reports", "debug", "libraries", "versions"];
+/// Top-level files that change while the server runs.
+const PLAYER_DATA: [&str; 5] = ["ops.json", "whitelist.json", "banned-players.json", "banned-ips.json", "usercache.json"];
+/// The vanilla server, which the Fabric launcher downloads itself.
+const VANILLA_SERVER: &str = "server.jar";
+
+/// Names in `dir`, sorted so the jetfuel.xml comes out the same every time.
+fn sorted_names(dir: &Path) -> Vec<String> {
+    let entries = fs::read_dir(dir)
+        .unwrap_or_else(|err| panic!("Failed to read {:?}: {err}", dir));
+
+    let mut names: Vec<_> = entries
+        .map(|entry| {
+            let entry = entry.unwrap_or_else(|err| panic!("Failed to read {:?}: {err}", dir));
+            entry.file_name().into_string()
+                .unwrap_or_else(|name| panic!("{:?} in {:?} is not UTF-8", name, dir))
+        })
+        .collect();
+    names.sort();
+    names
+}
+
+/// Adds every file under `relative` in `server_dir` to `files`.
+fn collect_files(server_dir: &Path, relative: &Path, files: &mut Vec<PathBuf>) {
+    let path = server_dir.join(relative);
+
+    if !path.is_dir() {
+        files.push(relative.to_path_buf());
+        return;
+    }
+
+    for name in sorted_names(&path) {
+        collect_files(server_dir, &relative.join(name), files);
+    }
+}
+
+/// Minecraft and Fabric Loader versions of a Fabric server launcher, read
+/// from the `install.properties` it carries. `None` for any other jar.
+fn fabric_launcher(path: &Path) -> Option<(String, String)> {
+    let file = File::open(path)
+        .unwrap_or_else(|err| panic!("Failed to open {:?}: {err}", path));
+    let mut archive = ZipArchive::new(file).ok()?;
+    let mut properties = String::new();
+    archive.by_name("install.properties").ok()?
+        .read_to_string(&mut properties)
+        .unwrap_or_else(|err| panic!("Failed to read install.properties in {:?}: {err}", path));
+
+    let properties: HashMap<_, _> = properties.lines()
+        .filter_map(|line| line.split_once('='))
+        .map(|(key, value)| (key.trim(), value.trim()))
+        .collect();
+
+    Some((properties.get("game-version")?.to_string(), properties.get("fabric-loader-version")?.to_string()))
+}
+
+/// The installer version in the name of a launcher for `minecraft` and
+/// `loader`, as downloaded from Fabric or saved by `jet expand`.
+fn installer_from_name(name: &str, minecraft: &str, loader: &str) -> Option<String> {
+    let name = name.strip_suffix(".jar")?;
+
+    name.strip_prefix(&format!("fabric-server-mc.{minecraft}-loader.{loader}-launcher."))
+        .or_else(|| name.strip_prefix(&format!("fabric-server.{minecraft}.{loader}.")))
+        .map(String::from)
+}
+
+/// Writes a jetfuel.xml describing the server in `server_dir`.
+pub async fn init<P : AsRef<Path>>(server_dir: P) {
+    let server_dir = server_dir.as_ref();
+
+    if !server_dir.is_dir() {
+        panic!("{:?} is not a directory", server_dir);
+    }
+
+    import::prepare_output(server_dir);
+
+    let client = jp::http_client();
+    let name = fs::canonicalize(server_dir).ok()
+        .and_then(|path| path.file_name().and_then(|name| name.to_str()).map(String::from))
+        .unwrap_or_else(|| "server".into());
+    let mut jetfuel = Jetfuel::new(ProjectInfo {
+        name,
+        description: String::new(),
+        version: "1.0.0".into(),
+        authors: vec![]
+    });
+
+    let mut jars = vec![];
+    let mut files = vec![];
+    let mut persist = vec![];
+    let mut fabric = None;
+
+    for name in sorted_names(server_dir) {
+        let path = server_dir.join(&name);
+
+        if name.starts_with('.') || (path.is_dir() && SKIPPED_DIRS.contains(&&name[..])) {
+            println!("{:>12} {}", "Skipped".yellow(), name);
+        } else if path.is_dir() && path.join("level.dat").is_file() {
+            println!("{:>12} {} (world)", "Persist".yellow(), name);
+            persist.push(name);
+        } else if path.is_dir() && name == "mods" {
+            for mod_name in sorted_names(&path) {
+                let relative = Path::new("mods").join(&mod_name);
+
+                if mod_name.ends_with(".jar") && server_dir.join(&relative).is_file() {
+                    jars.push(relative);
+                } else {
+                    collect_files(server_dir, &relative, &mut files);
+                }
+            }
+        } else if path.is_dir() {
+            collect_files(server_dir, Path::new(&name), &mut files);
+        } else if PLAYER_DATA.contains(&&name[..]) {
+            println!("{:>12} {} (player data)", "Persist".yellow(), name);
+            persist.push(name);
+        } else if name == VANILLA_SERVER
+            || name.ends_with(&format!(".{}", jp::EXTENSION))
+            || name.ends_with(&format!(".{}", jp_zlib::EXTENSION)) {
+            println!("{:>12} {}", "Skipped".yellow(), name);
+        } else if let Some(versions) = name.ends_with(".jar").then(|| fabric_launcher(&path)).flatten() {
+            println!("{:>12} {} (fabric {} on minecraft {})", "Found".green(), name, versions.1, versions.0);
+            fabric = Some((name, versions));
+        } else {
+            files.push(PathBuf::from(name));
+        }
+    }
+
+    match fabric {
+        Some((name, (minecraft, loader))) => {
+            let server = match installer_from_name(&name, &minecraft, &loader) {
+                Some(installer) => Some(Element::new("fabric-server")
+                    .attribute("minecraft", minecraft)
+                    .attribute("loader", loader)
+                    .attribute("installer", installer)),
+                None => import::fabric_server(&client, Some(&minecraft), Some(&loader)).await
+            };
+
+            if let Some(server) = server {
+                jetfuel.add(Path::new(""), server);
+            }
+        },
+        None => eprintln!("{}: no Fabric server launcher was found; add a <fabric-server> yourself", "warning".yellow())
+    }
+
+    let sha512s: Vec<_> = jars.iter()
+        .map(|jar| {
+            let path = server_dir.join(jar);
+            let bytes = fs::read(&path)
+                .unwrap_or_else(|err| panic!("Failed to read {:?}: {err}", path));
+            hex::encode(Sha512::digest(&bytes))
+        })
+        .collect();
+    let versions = modrinth::version_files_get(&client, &sha512s).await;
+
+    let project_ids: Vec<_> = versions.values()
+        .map(|version| version.project_id.clone())
+        .collect::<HashSet<_>>()
+        .into_iter()
+        .collect();
+    let slugs: HashMap<_, _> = modrinth::projects_get(&client, &project_ids).await.into_iter()
+        .map(|project| (project.id, project.slug))
+        .collect();
+
+    let mut from_modrinth = 0;
+
+    for (jar, sha512) in jars.iter().zip(&sha512s) {
+        let found = versions.get(sha512).and_then(|version| {
+            version.files.iter()
+                .find(|version_file| &version_file.hashes.sha512 == sha512)
+                .map(|version_file| (version, version_file))
+        });
+
+        let name = jar.file_name().and_then(|name| name.to_str()).expect("Jar names are UTF-8");
+
+        match found {
+            Some((version, version_file)) if import::can_keep_name(version_file, name) => {
+                let project = slugs.get(&version.project_id).unwrap_or(&version.project_id);
+                println!("{:>12} {} -> {} {}", "Found".green(), jar.to_str().unwrap(), project, version.version_number);
+
+                jetfuel.add(Path::new("mods"), import::modrinth_element(project, version, version_file));
+                from_modrinth += 1;
+            },
+            Some((_, version_file)) => {
+                eprintln!("{}: {} is called {} on Modrinth, so it will be embedded to keep its name",
+                    "warning".yellow(), jar.to_str().unwrap(), version_file.filename);
+                files.push(jar.clone());
+            },
+            None => {
+                eprintln!("{}: {} is not on Modrinth, so it will be embedded", "warning".yellow(), jar.to_str().unwrap());
+                files.push(jar.clone());
+            }
+        }
+    }
+
+    for file in &files {
+        let file_name = file.file_name().and_then(|name| name.to_str()).unwrap();
+        jetfuel.add(file.parent().unwrap_or(Path::new("")), Element::new("file").attribute("name", file_name));
+    }
+
+    for name in &persist {
+        jetfuel.add(Path::new(""), Element::new("persist").attribute("name", name));
+    }
+
+    jetfuel.write(server_dir);
+
+    println!("{:>12} {}: {} Modrinth mod(s), {} embedded file(s), {} persisted; review jetfuel.xml before packing",
+        "Initialized".green(),
+        server_dir.join("jetfuel.xml").to_str().unwrap(),
+        from_modrinth,
+        files.len(),
+        persist.len());
+}
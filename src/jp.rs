@@ -1,4 +1,4 @@
-use std::{io::{Write, Read, Seek, SeekFrom}, path::{PathBuf, Path}, fs, collections::HashMap, str::FromStr, sync::Arc, fmt::Display};
+use std::{io::{Write, Read, Seek, SeekFrom}, path::{PathBuf, Path}, fs, collections::{HashMap, HashSet, VecDeque}, str::FromStr, sync::{Arc, Mutex}, fmt::Display};
 use std::io::ErrorKind;
 
 use async_recursion::async_recursion;
@@ -6,15 +6,17 @@ use colored::Colorize;
 use futures::future::join_all;
 use once_cell::sync::Lazy;
 use pathdiff::diff_paths;
-use reqwest::{header::{HeaderValue, USER_AGENT, HeaderMap}, StatusCode};
-use sha2::{Sha512, Digest};
+use reqwest::{header::{HeaderValue, USER_AGENT, HeaderMap}, StatusCode, Url};
+use sha1::Sha1;
+use sha2::{Sha256, Sha512, Digest};
 use tar::Header;
 use serde::{Serialize, Deserialize};
 use tempfile::NamedTempFile;
+use tokio::sync::Semaphore;
 
-use crate::{modrinth::{VersionFile, self}, cached::{self, CacheState}};
+use crate::{modrinth::{VersionFile, VersionFileType, VersionType, DependencyEntry, DependencyType, ProjectGetResponse, ProjectVersionGetResponse, SideSupport, self}, maven, github, curseforge, cached::{self, CacheState}, config::CONFIG};
 
-pub const EXTENSION: &'static str = "jpk";
+pub const EXTENSION: &str = "jpk";
 
 static RUN_SCRIPT_MEM_PRESETS: Lazy<HashMap<String, String>> = Lazy::new(|| [
     ("none", ""),
@@ -56,7 +58,27 @@ fn parse_template(template: &str, options: &Options) -> String {
 }
 
 // Required by Modrinth
-const USER_AGENT_VALUE: &'static str = "der_fruhling/jet/0.1.0 (der_fruhling@outlook.com)";
+pub const USER_AGENT_VALUE: &str = "der_fruhling/jet/0.1.0 (der_fruhling@outlook.com)";
+
+pub fn http_client() -> reqwest::Client {
+    reqwest::Client::builder()
+        .default_headers(HeaderMap::from_iter([
+            (USER_AGENT, HeaderValue::from_static(USER_AGENT_VALUE))
+        ]))
+        .build().expect("Failed to build HTTP client")
+}
+
+/// Downloads `url` (through the cache) and returns the SHA-512 and size of what it serves now.
+pub async fn pin_sha512(client: &reqwest::Client, url: &str) -> (FileHash, usize) {
+    let (_, bytes) = cached::download(url, None, || async {
+        let response = github::download_request(client, url).send().await?.error_for_status()?;
+        Ok(response.bytes().await?.into())
+    }).await.unwrap_or_else(|err| panic!("Failed to download {} to pin its hash: {err}", url));
+
+    let hash = FileHash::Sha512(hex::encode(Sha512::digest(&bytes)));
+    println!("{:>12} {} (sha512: {})", "Pinned".green(), url, hash.hex());
+    (hash, bytes.len())
+}
 
 #[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Default, Clone)]
 #[serde(rename_all = "snake_case")]
@@ -73,7 +95,21 @@ pub enum ScriptType {
 pub enum Entry {
     Directory { name: String, contents: Vec<Entry> },
     File { name: String, hash: u128, size: usize },
-    Modrinth { project: String, version: String, files: Vec<VersionFile> },
+    Modrinth {
+        project: String,
+        version: String,
+        /// Missing in archives packed before versions were pinned by ID.
+        #[serde(default)]
+        version_id: Option<String>,
+        files: Vec<VersionFile>,
+        /// Save every file under its name on Modrinth, instead of naming the
+        /// primary one `<project>-<version>.<ext>`.
+        #[serde(default)]
+        keep_filename: bool
+    },
+    Download { name: String, url: String, hash: FileHash },
+    /// `url` is missing when the project's authors do not allow other programs to download it.
+    CurseForge { project: String, file_id: u32, name: String, url: Option<String>, hash: Option<FileHash>, page_url: String },
     FabricServerJar { minecraft_version: String, loader_version: String, installer_version: String },
     RunScript {
         name: String,
@@ -85,13 +121,88 @@ pub enum Entry {
 
 pub enum Action {
     CreateDir,
-    Extract { hash: u128, size: usize },
-    Download { display_name: String, url: String, sha512: Option<[u8; 64]> },
-    Symlink { source: PathBuf },
+    Extract { hash: u128 },
+    Download { display_name: String, url: String, hash: Option<FileHash> },
+    ManualDownload { display_name: String, page_url: String },
     RunScriptTemplate { source: &'static str, options: Options },
     Persist
 }
 
+/// A hash that downloaded contents must match, as a hex string.
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone)]
+#[serde(rename_all = "snake_case")]
+pub enum FileHash {
+    Sha1(String),
+    Sha256(String),
+    Sha512(String)
+}
+
+impl FileHash {
+    fn from_attributes(sha1: &Option<String>, sha256: &Option<String>, sha512: &Option<String>) -> Option<Self> {
+        let hash = match (sha1, sha256, sha512) {
+            (None, None, None) => return None,
+            (Some(hex), None, None) => FileHash::Sha1(hex.to_ascii_lowercase()),
+            (None, Some(hex), None) => FileHash::Sha256(hex.to_ascii_lowercase()),
+            (None, None, Some(hex)) => FileHash::Sha512(hex.to_ascii_lowercase()),
+            _ => panic!("Only one of sha1, sha256 and sha512 may be given")
+        };
+
+        Some(hash.validated())
+    }
+
+    /// Panics if this is not a hex string of the right length.
+    pub fn validated(self) -> Self {
+        let expected_len = match &self {
+            FileHash::Sha1(_) => 20,
+            FileHash::Sha256(_) => 32,
+            FileHash::Sha512(_) => 64
+        };
+
+        match hex::decode(self.hex()) {
+            Ok(bytes) if bytes.len() == expected_len => self,
+            _ => panic!("{} is not a valid {} hash", self.hex(), self.algorithm())
+        }
+    }
+
+    pub fn algorithm(&self) -> &'static str {
+        match self {
+            FileHash::Sha1(_) => "SHA-1",
+            FileHash::Sha256(_) => "SHA-256",
+            FileHash::Sha512(_) => "SHA-512"
+        }
+    }
+
+    /// Hashes `bytes` with the same algorithm as this hash.
+    pub fn digest(&self, bytes: &[u8]) -> String {
+        match self {
+            FileHash::Sha1(_) => hex::encode(Sha1::digest(bytes)),
+            FileHash::Sha256(_) => hex::encode(Sha256::digest(bytes)),
+            FileHash::Sha512(_) => hex::encode(Sha512::digest(bytes))
+        }
+    }
+
+    pub fn hex(&self) -> &str {
+        match self {
+            FileHash::Sha1(hex) | FileHash::Sha256(hex) | FileHash::Sha512(hex) => hex
+        }
+    }
+
+    pub fn matches(&self, bytes: &[u8]) -> bool {
+        self.digest(bytes).eq_ignore_ascii_case(self.hex())
+    }
+
+    /// The raw SHA-512, which the cache can use to find and check contents.
+    pub fn sha512(&self) -> Option<[u8; 64]> {
+        let FileHash::Sha512(hex) = self else {
+            return None;
+        };
+
+        Some(hex::decode(hex)
+            .expect("SHA-512 hash was not a valid hex string")
+            .try_into().expect("SHA-512 hash was an invalid length"))
+    }
+}
+
 #[derive(Serialize, Deserialize, PartialEq, Eq, Debug)]
 #[serde(deny_unknown_fields)]
 pub struct Manifest {
@@ -116,6 +227,14 @@ pub enum SourceRunOption {
     JavaArg(#[serde(rename = "$text")] String)
 }
 
+/// Which dependencies of a Modrinth version `jet pack` adds to the archive.
+#[derive(Deserialize, PartialEq, Eq, Debug, Default, Clone, Copy)]
+#[serde(rename_all = "kebab-case")]
+pub enum DependencyMode {
+    #[default] None,
+    Required
+}
+
 #[derive(Deserialize)]
 #[serde(rename_all = "kebab-case")]
 #[serde(deny_unknown_fields)]
@@ -137,10 +256,83 @@ pub enum SourceEntry {
     Modrinth {
         #[serde(rename = "@project")]
         project: String,
+        /// Exact version number or ID; without one, the newest version
+        /// matching the attributes below is picked.
         #[serde(rename = "@version")]
-        version: String
+        version: Option<String>,
+        /// Defaults to the Minecraft version of the pack's `<fabric-server>`.
+        #[serde(rename = "@game-version")]
+        game_version: Option<String>,
+        /// Defaults to `fabric` if the pack has a `<fabric-server>`.
+        #[serde(rename = "@loader")]
+        loader: Option<String>,
+        /// Least stable version type to pick; `release` if unset.
+        #[serde(rename = "@channel")]
+        channel: Option<VersionType>,
+        #[serde(rename = "@dependencies", default)]
+        dependencies: DependencyMode,
+        /// Wildcard pattern picking files by name instead of just the primary file.
+        #[serde(rename = "@files")]
+        files: Option<String>,
+        /// Picks files of this type instead of just the primary file.
+        #[serde(rename = "@file-type")]
+        file_type: Option<VersionFileType>,
+        #[serde(rename = "@keep-filename", default)]
+        keep_filename: bool
+    },
+
+    Download {
+        #[serde(rename = "@url")]
+        url: String,
+        /// Defaults to the last segment of the URL's path.
+        #[serde(rename = "@name")]
+        name: Option<String>,
+        #[serde(rename = "@sha1")]
+        sha1: Option<String>,
+        #[serde(rename = "@sha256")]
+        sha256: Option<String>,
+        #[serde(rename = "@sha512")]
+        sha512: Option<String>
     },
     
+    Maven {
+        #[serde(rename = "@repo")]
+        repo: String,
+        #[serde(rename = "@group")]
+        group: String,
+        #[serde(rename = "@artifact")]
+        artifact: String,
+        #[serde(rename = "@version")]
+        version: String,
+        #[serde(rename = "@classifier")]
+        classifier: Option<String>,
+        /// Defaults to `artifact-version[-classifier].jar`.
+        #[serde(rename = "@name")]
+        name: Option<String>
+    },
+
+    GithubRelease {
+        #[serde(rename = "@repo")]
+        repo: String,
+        #[serde(rename = "@tag")]
+        tag: String,
+        /// Wildcard pattern matching exactly one asset name.
+        #[serde(rename = "@asset")]
+        asset: String,
+        /// Defaults to the asset's name.
+        #[serde(rename = "@name")]
+        name: Option<String>
+    },
+
+    #[serde(rename = "curseforge")]
+    CurseForge {
+        /// Numeric ID or slug.
+        #[serde(rename = "@project")]
+        project: String,
+        #[serde(rename = "@file")]
+        file: String
+    },
+
     FabricServer {
         #[serde(rename = "@minecraft")]
         minecraft_version: String,
@@ -170,7 +362,7 @@ pub struct ProjectInfo {
     pub name: String,
     pub description: String,
     pub version: String,
-    #[serde(rename = "author")]
+    #[serde(rename = "author", default)]
     pub authors: Vec<String>,
 }
 
@@ -183,19 +375,294 @@ pub struct SourceManifest {
     pub contents: Vec<SourceEntry>
 }
 
+/// State shared by every entry while a manifest is parsed.
+struct ParseContext {
+    client: reqwest::Client,
+    /// Game version and loader of the pack, from its `<fabric-server>`.
+    game_version: Option<String>,
+    loader: Option<String>,
+    /// Modrinth projects listed in the jetfuel, as written there (slug or ID).
+    listed: HashSet<String>,
+    /// Project IDs of the listed Modrinth projects, once prefetched.
+    listed_ids: Mutex<HashSet<String>>,
+    /// Dependencies of versions whose dependencies are resolved, by project.
+    dependencies: Mutex<HashMap<String, Vec<DependencyEntry>>>,
+    /// Project IDs of dependencies added so far.
+    added: Mutex<HashSet<String>>,
+    /// Project and version IDs present in the pack, to the name they were added as.
+    present: Mutex<HashMap<String, String>>,
+    /// Incompatible dependencies of resolved versions, and what they were found in.
+    incompatible: Mutex<Vec<(String, DependencyEntry)>>,
+    /// Fail on compatibility problems instead of warning about them.
+    strict: bool,
+    /// Number of compatibility problems warned about.
+    problems: Mutex<usize>,
+    /// Modrinth versions (by ID) and projects (by project ID) fetched in bulk
+    /// ahead of time, taken out as entries use them.
+    prefetched_versions: Mutex<HashMap<String, ProjectVersionGetResponse>>,
+    prefetched_projects: Mutex<HashMap<String, ProjectGetResponse>>
+}
+
+impl ParseContext {
+    fn new(manifest: &SourceManifest, strict: bool) -> Self {
+        fn visit(entry: &SourceEntry, context: &mut ParseContext) {
+            match entry {
+                SourceEntry::Directory { contents, .. } => for child in contents {
+                    visit(child, context);
+                },
+                SourceEntry::Modrinth { project, .. } => {
+                    context.listed.insert(project.clone());
+                },
+                SourceEntry::FabricServer { minecraft_version, .. } => {
+                    context.game_version.get_or_insert_with(|| minecraft_version.clone());
+                    context.loader.get_or_insert_with(|| "fabric".into());
+                },
+                _ => {}
+            }
+        }
+
+        let mut context = Self {
+            client: http_client(),
+            game_version: None,
+            loader: None,
+            listed: HashSet::new(),
+            listed_ids: Mutex::default(),
+            dependencies: Mutex::default(),
+            added: Mutex::default(),
+            present: Mutex::default(),
+            incompatible: Mutex::default(),
+            strict,
+            problems: Mutex::default(),
+            prefetched_versions: Mutex::default(),
+            prefetched_projects: Mutex::default()
+        };
+
+        for entry in &manifest.contents {
+            visit(entry, &mut context);
+        }
+
+        context
+    }
+
+    /// Fetches what the listed Modrinth entries need in a few bulk requests,
+    /// instead of a couple of requests per entry. Versions given by number
+    /// still need a request each, as there is no bulk endpoint for those.
+    async fn prefetch_modrinth(&self, manifest: &SourceManifest) {
+        fn visit(entry: &SourceEntry, version_ids: &mut Vec<String>) {
+            match entry {
+                SourceEntry::Directory { contents, .. } => for child in contents {
+                    visit(child, version_ids);
+                },
+                SourceEntry::Modrinth { version: Some(version), .. } if modrinth::is_version_id(version) => {
+                    version_ids.push(version.clone());
+                },
+                _ => {}
+            }
+        }
+
+        let mut version_ids = vec![];
+        for entry in &manifest.contents {
+            visit(entry, &mut version_ids);
+        }
+
+        if !version_ids.is_empty() {
+            let versions = modrinth::versions_get(&self.client, &version_ids).await;
+            self.prefetched_versions.lock().unwrap()
+                .extend(versions.into_iter().map(|version| (version.id.clone(), version)));
+        }
+
+        if !self.listed.is_empty() {
+            let listed: Vec<_> = self.listed.iter().cloned().collect();
+            let projects = modrinth::projects_get(&self.client, &listed).await;
+            self.listed_ids.lock().unwrap()
+                .extend(projects.iter().map(|project| project.id.clone()));
+            self.prefetched_projects.lock().unwrap()
+                .extend(projects.into_iter().map(|project| (project.id.clone(), project)));
+        }
+    }
+
+    /// Whether the project is listed in the jetfuel, however it is written there.
+    fn is_listed(&self, project: &ProjectGetResponse) -> bool {
+        self.listed_ids.lock().unwrap().contains(&project.id)
+            || self.listed.iter().any(|listed| *listed == project.id || listed.eq_ignore_ascii_case(&project.slug))
+    }
+
+    async fn modrinth_version(&self, project: &str, version: &str) -> ProjectVersionGetResponse {
+        let prefetched = self.prefetched_versions.lock().unwrap().remove(version);
+
+        match prefetched {
+            Some(version_resp) => {
+                // the bulk lookup goes by version ID alone, so check it is the project's
+                if version_resp.project_id != project {
+                    let slug = self.prefetched_projects.lock().unwrap()
+                        .get(&version_resp.project_id)
+                        .map(|project_resp| project_resp.slug.clone());
+                    let slug = match slug {
+                        Some(slug) => slug,
+                        None => modrinth::project_get(&self.client, &version_resp.project_id).await.slug
+                    };
+
+                    if !slug.eq_ignore_ascii_case(project) {
+                        panic!("Modrinth version {} belongs to {}, not {}", version, slug, project);
+                    }
+                }
+
+                version_resp
+            },
+            None => {
+                // need to resolve project id and version id into slugs
+                let version_resp = modrinth::project_version_get(&self.client, project, version).await;
+                println!("{:>12} {} {} [version info]", "GET".magenta(), project, version);
+                version_resp
+            }
+        }
+    }
+
+    async fn modrinth_project(&self, project_id: &str) -> ProjectGetResponse {
+        let prefetched = self.prefetched_projects.lock().unwrap().remove(project_id);
+
+        match prefetched {
+            Some(project_resp) => project_resp,
+            None => modrinth::project_get(&self.client, project_id).await
+        }
+    }
+
+    /// Warns if a Modrinth version does not belong on this server.
+    fn check_modrinth(&self, project: &ProjectGetResponse, version: &ProjectVersionGetResponse) {
+        let mut problems = vec![];
+
+        if let SideSupport::Unsupported = project.server_side {
+            problems.push("does not support servers".to_string());
+        }
+
+        if let Some(loader) = &self.loader {
+            if !version.loaders.contains(loader) {
+                problems.push(format!("does not support {} (only {})", loader, version.loaders.join(", ")));
+            }
+        }
+
+        if let Some(game_version) = &self.game_version {
+            if !version.game_versions.contains(game_version) {
+                problems.push(format!("is not made for Minecraft {} (only {})", game_version, version.game_versions.join(", ")));
+            }
+        }
+
+        for problem in &problems {
+            eprintln!("{}: {} {} {}", "warning".yellow(), project.slug, version.version_number, problem);
+        }
+
+        *self.problems.lock().unwrap() += problems.len();
+    }
+}
+
 impl Entry {
+    /// Parses `contents` followed by the dependencies they ask for.
+    async fn parse_all(contents: &[SourceEntry], context: &ParseContext) -> Vec<Self> {
+        let mut entries = join_all(contents.iter().map(|entry| Entry::parse(entry, context))).await;
+
+        for entry in contents {
+            if let SourceEntry::Modrinth { project, dependencies: DependencyMode::Required, .. } = entry {
+                entries.extend(Entry::resolve_dependencies(project, context).await);
+            }
+        }
+
+        entries
+    }
+
+    /// Walks the required dependencies of a Modrinth version, returning the
+    /// projects that are not in the pack yet.
+    async fn resolve_dependencies(project: &str, context: &ParseContext) -> Vec<Self> {
+        let client = &context.client;
+        let mut entries = vec![];
+        let mut queue: VecDeque<_> = context.dependencies.lock().unwrap()
+            .remove(project)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|dependency| (project.to_string(), dependency))
+            .collect();
+
+        while let Some((required_by, dependency)) = queue.pop_front() {
+            match dependency.dependency_type {
+                DependencyType::Required => {},
+                DependencyType::Incompatible => {
+                    context.incompatible.lock().unwrap().push((required_by, dependency));
+                    continue;
+                },
+                DependencyType::Optional | DependencyType::Embedded => continue
+            }
+
+            let already_present = dependency.project_id.as_ref()
+                .is_some_and(|project_id| context.present.lock().unwrap().contains_key(project_id));
+
+            if already_present {
+                continue;
+            }
+
+            let version_resp = match (&dependency.version_id, &dependency.project_id) {
+                (Some(version_id), _) => modrinth::version_get(client, version_id).await,
+                (None, Some(project_id)) => {
+                    let (Some(game_version), Some(loader)) = (&context.game_version, &context.loader) else {
+                        panic!("{} requires {} without naming a version; picking one needs a <fabric-server>", required_by, project_id);
+                    };
+
+                    // libraries are often only published as betas, so settle for one if there is no release
+                    modrinth::newest_version(modrinth::project_versions_get(client, project_id, game_version, loader).await, VersionType::Alpha)
+                        .unwrap_or_else(|| panic!("{} requires {}, which has no version for {} {}", required_by, project_id, loader, game_version))
+                },
+                (None, None) => {
+                    eprintln!("{}: {} requires a file that is not on Modrinth ({}); add it yourself",
+                        "warning".yellow(), required_by, dependency.file_name.as_deref().unwrap_or("unknown name"));
+                    continue;
+                }
+            };
+
+            let project_resp = context.modrinth_project(&version_resp.project_id).await;
+
+            if context.is_listed(&project_resp) {
+                continue; // listed explicitly, and that version wins
+            }
+
+            if !context.added.lock().unwrap().insert(project_resp.id.clone()) {
+                continue;
+            }
+
+            println!("{:>12} {} {} (required by {})", "Dependency".green(), project_resp.slug, version_resp.version_number, required_by);
+            context.check_modrinth(&project_resp, &version_resp);
+
+            {
+                let mut present = context.present.lock().unwrap();
+                present.insert(project_resp.id.clone(), project_resp.slug.clone());
+                present.insert(version_resp.id.clone(), format!("{} {}", project_resp.slug, version_resp.version_number));
+            }
+
+            for dependency in version_resp.dependencies.into_iter().flatten() {
+                queue.push_back((project_resp.slug.clone(), dependency));
+            }
+
+            entries.push(Entry::Modrinth {
+                project: project_resp.slug,
+                version: version_resp.version_number,
+                version_id: Some(version_resp.id),
+                files: modrinth::select_files(version_resp.files, None, None),
+                keep_filename: false
+            });
+        }
+
+        entries
+    }
+
     #[async_recursion]
-    async fn parse(value: &SourceEntry) -> Self {
+    async fn parse(value: &SourceEntry, context: &ParseContext) -> Self {
         match value {
             SourceEntry::Directory { name, contents } => {
                 Entry::Directory {
                     name: name.clone(),
-                    contents: join_all(contents.iter().map(Entry::parse)).await
+                    contents: Entry::parse_all(contents, context).await
                 }
             },
             SourceEntry::File { name, source_path } => {
                 let data = fs::read(source_path.as_ref().unwrap())
-                    .expect(format!("Failed to read file: {:?}", source_path.as_ref().unwrap().to_str()).as_str());
+                    .unwrap_or_else(|err| panic!("Failed to read file: {:?}: {err}", source_path.as_ref().unwrap().to_str()));
                 
                 Entry::File {
                     name: name.clone(),
@@ -203,21 +670,139 @@ impl Entry {
                     size: data.len()
                 }
             },
-            SourceEntry::Modrinth { project, version } => {
-                // need to resolve project id and version id into slugs
-                let client = reqwest::Client::builder()
-                    .default_headers(HeaderMap::from_iter([
-                        (USER_AGENT, HeaderValue::from_static(USER_AGENT_VALUE))
-                    ]))
-                    .build().expect("Failed to build HTTP client");
-                
-                let version_resp = modrinth::project_version_get(&client, project, version).await;
-                println!("{:>12} {} {} [version info]", "GET".magenta(), project, version);
+            SourceEntry::Modrinth { project, version, game_version, loader, channel, dependencies, files, file_type, keep_filename } => {
+                if file_type == &Some(VersionFileType::Unknown) {
+                    panic!("Modrinth project {} has an unknown file-type; use required-resource-pack or optional-resource-pack", project);
+                }
+
+                let version_resp = match version {
+                    Some(version) => context.modrinth_version(project, version).await,
+                    None => {
+                        let game_version = game_version.as_ref().or(context.game_version.as_ref())
+                            .unwrap_or_else(|| panic!("Modrinth project {} needs a version, or a game-version to pick one for", project));
+                        let loader = loader.as_ref().or(context.loader.as_ref())
+                            .unwrap_or_else(|| panic!("Modrinth project {} needs a version, or a loader to pick one for", project));
+                        let channel = channel.unwrap_or(VersionType::Release);
+
+                        let versions = modrinth::project_versions_get(&context.client, project, game_version, loader).await;
+                        let version_resp = modrinth::newest_version(versions, channel)
+                            .unwrap_or_else(|| panic!("Modrinth project {} has no {:?} version for {} {}", project, channel, loader, game_version));
+                        println!("{:>12} {} -> {} ({})", "Resolved".green(), project, version_resp.version_number, version_resp.id);
+                        version_resp
+                    }
+                };
+
+                let project_resp = context.modrinth_project(&version_resp.project_id).await;
+                context.check_modrinth(&project_resp, &version_resp);
+
+                {
+                    let mut present = context.present.lock().unwrap();
+                    present.insert(version_resp.project_id.clone(), project.clone());
+                    present.insert(version_resp.id.clone(), format!("{} {}", project, version_resp.version_number));
+                }
+
+                if *dependencies == DependencyMode::Required {
+                    context.dependencies.lock().unwrap()
+                        .insert(project.clone(), version_resp.dependencies.unwrap_or_default());
+                }
                 
+                let version_number = version.clone().unwrap_or(version_resp.version_number);
+                let selected = modrinth::select_files(version_resp.files, files.as_deref(), file_type.as_ref());
+
+                if selected.is_empty() {
+                    panic!("No file of Modrinth project {} {} matches the entry's files or file-type", project, version_number);
+                }
+
                 Entry::Modrinth {
                     project: project.clone(),
-                    version: version.clone(),
-                    files: version_resp.files
+                    version: version_number,
+                    version_id: Some(version_resp.id),
+                    files: selected,
+                    keep_filename: *keep_filename
+                }
+            },
+            SourceEntry::Download { url, name, sha1, sha256, sha512 } => {
+                let name = name.clone().unwrap_or_else(|| Url::parse(url).ok()
+                    .and_then(|url| url.path_segments()?.next_back().filter(|name| !name.is_empty()).map(String::from))
+                    .unwrap_or_else(|| panic!("Cannot tell a file name from {}; give the download a name", url)));
+
+                let hash = match FileHash::from_attributes(sha1, sha256, sha512) {
+                    Some(hash) => hash,
+                    // nothing to check the file against, so pin whatever is there now
+                    None => pin_sha512(&context.client, url).await.0
+                };
+
+                Entry::Download {
+                    name,
+                    url: url.clone(),
+                    hash
+                }
+            },
+            SourceEntry::Maven { repo, group, artifact, version, classifier, name } => {
+                let coordinates = maven::Coordinates {
+                    repo,
+                    group,
+                    artifact,
+                    version,
+                    classifier: classifier.as_deref()
+                };
+                let resolved = coordinates.resolve(&context.client).await;
+
+                Entry::Download {
+                    name: name.clone().unwrap_or_else(|| coordinates.file_name()),
+                    url: resolved.url,
+                    hash: resolved.hash
+                }
+            },
+            SourceEntry::GithubRelease { repo, tag, asset, name } => {
+                let client = &context.client;
+                let release = github::release_get(client, repo, tag).await;
+                let asset = release.asset(repo, asset);
+                let url = asset.download_url();
+                let (hash, size) = pin_sha512(client, url).await;
+
+                if size != asset.size {
+                    panic!("{} is {} bytes, but GitHub says it is {}", url, size, asset.size);
+                }
+
+                Entry::Download {
+                    name: name.clone().unwrap_or_else(|| asset.name.clone()),
+                    url: url.to_string(),
+                    hash
+                }
+            },
+            SourceEntry::CurseForge { project, file } => {
+                let client = &context.client;
+                let project_resp = curseforge::mod_get(client, project).await;
+                let file_resp = curseforge::mod_file_get(client, project_resp.id, file).await;
+                println!("{:>12} {} {} [file info]", "GET".magenta(), project, file);
+
+                let page_url = project_resp.links.website_url.clone()
+                    .unwrap_or_else(|| format!("https://www.curseforge.com/minecraft/mc-mods/{}", project_resp.slug));
+                let page_url = format!("{}/files/{}", page_url.trim_end_matches('/'), file_resp.id);
+
+                // the project-wide setting wins even if the API still hands out a URL
+                let url = file_resp.download_url.clone()
+                    .filter(|_| project_resp.allow_mod_distribution != Some(false));
+
+                let hash = match (file_resp.sha1(), &url) {
+                    (Some(sha1), _) => Some(FileHash::Sha1(sha1.to_ascii_lowercase()).validated()),
+                    (None, Some(url)) => Some(pin_sha512(client, url).await.0),
+                    (None, None) => None
+                };
+
+                if url.is_none() {
+                    eprintln!("{}: the authors of {} do not allow other programs to download it; expanding will ask for {} to be downloaded by hand",
+                        "warning".yellow(), project_resp.name, file_resp.file_name);
+                }
+
+                Entry::CurseForge {
+                    project: project_resp.slug,
+                    file_id: file_resp.id,
+                    name: file_resp.file_name,
+                    url,
+                    hash,
+                    page_url
                 }
             },
             SourceEntry::FabricServer { minecraft_version, loader_version, installer_version } => {
@@ -257,10 +842,30 @@ impl Entry {
 }
 
 impl Manifest {
-    pub async fn parse(value: &SourceManifest) -> Self {
+    pub async fn parse(value: &SourceManifest, strict: bool) -> Self {
+        let context = ParseContext::new(value, strict);
+        context.prefetch_modrinth(value).await;
+        let contents = Entry::parse_all(&value.contents, &context).await;
+
+        let problems = *context.problems.lock().unwrap();
+        if context.strict && problems > 0 {
+            panic!("Not packing with --strict: {} compatibility problem(s) above", problems);
+        }
+
+        let present = context.present.lock().unwrap();
+        for (found_in, dependency) in context.incompatible.lock().unwrap().iter() {
+            let conflict = [&dependency.project_id, &dependency.version_id].into_iter()
+                .flatten()
+                .find_map(|id| present.get(id));
+
+            if let Some(conflict) = conflict {
+                panic!("{} is incompatible with {}, but both are in the pack", found_in, conflict);
+            }
+        }
+
         Self {
             project_info: value.project.clone(),
-            contents: join_all(value.contents.iter().map(Entry::parse)).await
+            contents
         }
     }
 }
@@ -286,6 +891,10 @@ impl SourceEntry {
                 }
             },
             SourceEntry::Modrinth { .. } => {}, // nothing to resolve
+            SourceEntry::Download { .. } => {}, // nothing to resolve
+            SourceEntry::Maven { .. } => {}, // nothing to resolve
+            SourceEntry::GithubRelease { .. } => {}, // nothing to resolve
+            SourceEntry::CurseForge { .. } => {}, // nothing to resolve
             SourceEntry::FabricServer { .. } => {}, // nothing to resolve
             SourceEntry::RunScript { .. } => {}, // nothing to resolve
             SourceEntry::Persist { .. } => {} // nothing to resolve
@@ -302,6 +911,16 @@ impl SourceManifest {
 }
 
 impl Manifest {
+    /// Every URL that `jet expand` would download for this manifest.
+    pub fn download_urls(&self) -> Vec<String> {
+        self.as_actions("").0.into_iter()
+            .filter_map(|(_, action)| match action {
+                Action::Download { url, .. } => Some(url),
+                _ => None
+            })
+            .collect()
+    }
+
     fn as_actions<P : AsRef<Path>>(&self, base_dir: P) -> (Vec<(PathBuf, Action)>, Option<PathBuf>) {
         let mut actions = vec![];
 
@@ -314,34 +933,48 @@ impl Manifest {
                         Entry::Directory { name, .. } => path.join(name),
                         Entry::File { name, .. } => path.join(name),
                         Entry::Modrinth { .. } => path.to_path_buf(), // projects can have multiple files
+                        Entry::Download { name, .. } => path.join(name),
+                        Entry::CurseForge { name, .. } => path.join(name),
                         Entry::FabricServerJar { .. } => path.to_path_buf(), // TODO resolve
                         Entry::RunScript { .. } => path.to_path_buf(), // name can be templated
                         Entry::Persist { name } => path.join(name)
                     })
                 },
                 
-                Entry::File { hash, size, .. } => {
-                    actions.push((path, Action::Extract { hash: *hash, size: *size}));
+                Entry::File { hash, .. } => {
+                    actions.push((path, Action::Extract { hash: *hash }));
                 },
                 
-                Entry::Modrinth { project, version, files } => {
+                Entry::Modrinth { project, version, files, keep_filename, .. } => {
                     for file in files {
                         actions.push((
-                            path.join(format!("{}-{}.{}",
-                                project, version,
-                                Path::new(&file.filename).extension().unwrap().to_str().unwrap()
-                            )),
+                            path.join(modrinth_file_name(project, version, file, *keep_filename)),
                             Action::Download {
                                 display_name: format!("modrinth [{}-{}::{}]", project, version, file.filename),
                                 url: file.url.clone(),
-                                sha512: Some(hex::decode(&file.hashes.sha512)
-                                    .expect("SHA-512 hash was not a valid hex string")
-                                    .try_into().expect("SHA-512 hash was an invalid length"))
+                                hash: Some(FileHash::Sha512(file.hashes.sha512.clone()))
                             }
                         ))
                     }
                 },
+
+                Entry::Download { name, url, hash } => {
+                    actions.push((path, Action::Download {
+                        display_name: format!("download [{}]", name),
+                        url: url.clone(),
+                        hash: Some(hash.clone())
+                    }));
+                },
                 
+                Entry::CurseForge { project, file_id, name, url, hash, page_url } => {
+                    let display_name = format!("curseforge [{}::{}]", project, name);
+
+                    actions.push((path, match url {
+                        Some(url) => Action::Download { display_name, url: url.clone(), hash: hash.clone() },
+                        None => Action::ManualDownload { display_name: format!("{} (file {})", display_name, file_id), page_url: page_url.clone() }
+                    }));
+                },
+
                 Entry::FabricServerJar {
                     minecraft_version,
                     loader_version,
@@ -353,7 +986,7 @@ impl Manifest {
                         Action::Download {
                             display_name: format!("fabric server [{}-{}, installer {}]", minecraft_version, loader_version, installer_version),
                             url: format!("https://meta.fabricmc.net/v2/versions/loader/{}/{}/{}/server/jar", minecraft_version, loader_version, installer_version),
-                            sha512: None // fabric server does not provide hashes afaik
+                            hash: None // fabric server does not provide hashes afaik
                         }
                     ));
                     
@@ -392,6 +1025,8 @@ impl Manifest {
                 Entry::Directory { name, .. } => base_dir.as_ref().join(name),
                 Entry::File { name, .. } => base_dir.as_ref().join(name),
                 Entry::Modrinth { .. } => base_dir.as_ref().to_path_buf(), // projects can have multiple files
+                Entry::Download { name, .. } => base_dir.as_ref().join(name),
+                Entry::CurseForge { name, .. } => base_dir.as_ref().join(name),
                 Entry::FabricServerJar { .. } => base_dir.as_ref().to_path_buf(), // TODO resolve
                 Entry::RunScript { .. } => base_dir.as_ref().to_path_buf(), // name can be templated
                 Entry::Persist { name } => base_dir.as_ref().join(name)
@@ -402,17 +1037,30 @@ impl Manifest {
     }
 }
 
+/// The name `jet expand` saves a file of a Modrinth entry as. Only the primary
+/// file is named after the project, so they cannot clash.
+pub fn modrinth_file_name(project: &str, version: &str, file: &VersionFile, keep_filename: bool) -> String {
+    if keep_filename || !file.primary {
+        return file.filename.clone();
+    }
+
+    format!("{}-{}.{}",
+        project, version,
+        Path::new(&file.filename).extension().unwrap().to_str().unwrap()
+    )
+}
+
 fn add_data<W : Write, R : Read>(builder: &mut tar::Builder<W>, path: &str, mut contents: R) {
     let mut vec = Vec::new();
     contents.read_to_end(&mut vec)
-        .expect(format!("Failed to read for file {}", path).as_str());
+        .unwrap_or_else(|err| panic!("Failed to read for file {}: {err}", path));
     
     let mut header = Header::new_gnu();
     header.set_size(vec.len() as u64);
     header.set_cksum();
     
     builder.append_data(&mut header, path, &vec[..])
-        .expect(format!("Failed to append file {}", path).as_str());
+        .unwrap_or_else(|err| panic!("Failed to append file {}: {err}", path));
 }
 
 fn recurse_files<F : FnMut(&SourceEntry)>(entry: &SourceEntry, f: &mut F) {
@@ -427,7 +1075,7 @@ fn recurse_files<F : FnMut(&SourceEntry)>(entry: &SourceEntry, f: &mut F) {
     }
 }
 
-pub async fn pack<W : Write, P1 : AsRef<Path>, P2 : AsRef<Path>>(writer: W, manifest_path: Option<P1>, mut manifest: SourceManifest, source_dir: P2) {
+pub async fn pack<W : Write, P1 : AsRef<Path>, P2 : AsRef<Path>>(writer: W, manifest_path: Option<P1>, mut manifest: SourceManifest, source_dir: P2, strict: bool) {
     manifest.resolve(source_dir);
     
     let mut builder = tar::Builder::new(writer);
@@ -436,7 +1084,7 @@ pub async fn pack<W : Write, P1 : AsRef<Path>, P2 : AsRef<Path>>(writer: W, mani
     println!("{:>12} @manifest", "Generating".green());
     
     let mut data = Vec::new();
-    ciborium::into_writer(&Manifest::parse(&manifest).await, &mut data)
+    ciborium::into_writer(&Manifest::parse(&manifest, strict).await, &mut data)
         .expect("Failed to serialize manifest");
 
     println!("{:>12} @manifest", "Writing".yellow());
@@ -444,7 +1092,7 @@ pub async fn pack<W : Write, P1 : AsRef<Path>, P2 : AsRef<Path>>(writer: W, mani
     
     if let Some(path) = manifest_path {
         println!("{:>12} @jetfuel.xml", "Writing".yellow());
-        let data = fs::read(&path).expect(&format!("Failed to read from {}", path.as_ref().to_str().unwrap()));
+        let data = fs::read(&path).unwrap_or_else(|err| panic!("Failed to read from {}: {err}", path.as_ref().to_str().unwrap()));
         add_data(&mut builder, "@jetfuel.xml", &data[..]);
     }
     
@@ -455,10 +1103,10 @@ pub async fn pack<W : Write, P1 : AsRef<Path>, P2 : AsRef<Path>>(writer: W, mani
             };
 
             print!("{:>12} {}", "Embedding".yellow(), source_path.as_ref().unwrap()
-                .to_str().unwrap_or_else(|| "<unknown>"));
+                .to_str().unwrap_or("<unknown>"));
             
             let data = fs::read(source_path.as_ref().unwrap())
-                .expect(format!("Failed to read {:?}", source_path.as_ref().unwrap().to_str()).as_str());
+                .unwrap_or_else(|err| panic!("Failed to read {:?}: {err}", source_path.as_ref().unwrap().to_str()));
             let hash = meowhash::MeowHasher::hash(&data[..]);
             let filename = format!("{:032x}", hash.as_u128());
             
@@ -469,7 +1117,7 @@ pub async fn pack<W : Write, P1 : AsRef<Path>, P2 : AsRef<Path>>(writer: W, mani
             header.set_cksum();
             
             builder.append_data(&mut header, &filename, &data[..])
-                .expect(format!("Failed to append data hash {}", &filename).as_str());
+                .unwrap_or_else(|err| panic!("Failed to append data hash {}: {err}", &filename));
             println!()
         });
     }
@@ -520,7 +1168,99 @@ pub fn unpack_selective<R : Read>(reader: R, name: &str) -> Option<Vec<u8>> {
     None
 }
 
-pub async fn expand<R : Read, P : AsRef<Path>>(reader: R, target_dir: P) {
+pub fn read_manifest<R : Read>(reader: R) -> Option<Manifest> {
+    let bytes = unpack_selective(reader, "@manifest")?;
+
+    match ciborium::from_reader(&bytes[..]) {
+        Ok(manifest) => Some(manifest),
+        Err(err) => {
+            eprintln!("{}: error reading @manifest: {}", "warning".yellow(), err);
+            None
+        }
+    }
+}
+
+/// Reads the manifest of an archive along with every file embedded in it,
+/// keyed by hash.
+pub fn read_archive<R : Read>(reader: R) -> Option<(Manifest, HashMap<u128, Vec<u8>>)> {
+    let mut archive = tar::Archive::new(reader);
+    let mut manifest = None;
+    let mut embedded = HashMap::new();
+
+    for entry in archive.entries().expect("Failed to read entries from tar archive") {
+        let mut entry = entry.expect("Failed to read tar entry");
+        let name = entry.path().unwrap().to_str().expect("Strange path could not be converted to string").to_string();
+
+        let mut buf = Vec::new();
+        entry.read_to_end(&mut buf).expect("Failed to read tar entry data");
+
+        if name == "@manifest" {
+            match ciborium::from_reader(&buf[..]) {
+                Ok(read) => manifest = Some(read),
+                Err(err) => {
+                    eprintln!("{}: error reading @manifest: {}", "warning".yellow(), err);
+                    return None;
+                }
+            }
+        } else if let Ok(hash) = u128::from_str_radix(&name, 16) {
+            embedded.insert(hash, buf);
+        }
+    }
+
+    Some((manifest?, embedded))
+}
+
+/// How `jet expand` places downloaded files into the target directory.
+#[derive(clap::ValueEnum, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum LinkMode {
+    /// Write a separate copy of every file.
+    Copy,
+    /// Hardlink files to the cache's copy, so every expanded target shares it.
+    /// Anything that modifies such a file in place modifies the cache too.
+    Hardlink,
+    /// Clone the cache's copy on filesystems that support it (btrfs, XFS, ...).
+    Reflink
+}
+
+/// Places the cached contents `hash` at `target` as `mode` asks. Returns false
+/// if that is not possible, and the caller should copy the file instead.
+fn link_from_cache(mode: LinkMode, hash: u128, target: &Path) -> bool {
+    let source = cached::contents_path(hash);
+
+    match mode {
+        LinkMode::Copy => false,
+        LinkMode::Hardlink => fs::hard_link(&source, target).is_ok(),
+        LinkMode::Reflink => reflink(&source, target).is_ok()
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn reflink(source: &Path, target: &Path) -> std::io::Result<()> {
+    use std::os::fd::AsRawFd;
+
+    // from linux/fs.h
+    const FICLONE: libc::c_ulong = 0x40049409;
+
+    let source = fs::File::open(source)?;
+    let target_file = fs::File::create(target)?;
+
+    // SAFETY: both descriptors are open for as long as the call runs
+    if unsafe { libc::ioctl(target_file.as_raw_fd(), FICLONE as _, source.as_raw_fd()) } != 0 {
+        let err = std::io::Error::last_os_error();
+        drop(target_file);
+        let _ = fs::remove_file(target);
+        return Err(err);
+    }
+
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn reflink(_source: &Path, _target: &Path) -> std::io::Result<()> {
+    Err(std::io::Error::new(ErrorKind::Unsupported, "reflinks are only supported on Linux"))
+}
+
+pub async fn expand<R : Read, P : AsRef<Path>>(reader: R, target_dir: P, link_mode: LinkMode) {
     fs::create_dir_all(target_dir.as_ref()).expect("Failed to create directory");
     
     let mut archive = tar::Archive::new(reader);
@@ -555,21 +1295,18 @@ pub async fn expand<R : Read, P : AsRef<Path>>(reader: R, target_dir: P) {
     
     let mut extract_map = HashMap::<PathBuf, PathBuf>::new();
     let mut join_handles = vec![];
-    let client = Arc::new(reqwest::Client::builder()
-        .default_headers(HeaderMap::from_iter([
-            (USER_AGENT, HeaderValue::from_static(USER_AGENT_VALUE))
-        ]))
-        .build()
-        .expect("Failed to build HTTP client"));
+    let client = Arc::new(http_client());
+    let download_limit = Arc::new(Semaphore::new(CONFIG.download.concurrency.unwrap_or(Semaphore::MAX_PERMITS)));
     
     let (actions, server_path) = manifest.as_actions(&target_dir);
+    let mut manual_downloads = vec![];
     
     for (path, action) in &actions {
         if let Action::Persist = action {
             if path.is_file() {
                 match fs::File::open(path) {
                     Ok(mut file) => {
-                        persist.append_file(diff_paths(path, &target_dir).unwrap_or_else(|| path.clone()), &mut file).expect(&format!("Failed to persist file {}", path.to_str().unwrap()));
+                        persist.append_file(diff_paths(path, &target_dir).unwrap_or_else(|| path.clone()), &mut file).unwrap_or_else(|err| panic!("Failed to persist file {}: {err}", path.to_str().unwrap()));
                         println!("{:>12} {}", "Persist".yellow(), path.to_str().unwrap());
                     }
 
@@ -582,7 +1319,7 @@ pub async fn expand<R : Read, P : AsRef<Path>>(reader: R, target_dir: P) {
                     }
                 }
             } else if path.is_dir() {
-                persist.append_dir_all(diff_paths(path, &target_dir).unwrap_or_else(|| path.clone()), path).expect(&format!("Failed to persist directory {path:?}"))
+                persist.append_dir_all(diff_paths(path, &target_dir).unwrap_or_else(|| path.clone()), path).unwrap_or_else(|err| panic!("Failed to persist directory {path:?}: {err}"))
             } else {
                 eprintln!("Cannot persist file {path:?}! Must be a file or directory.")
             }
@@ -596,16 +1333,22 @@ pub async fn expand<R : Read, P : AsRef<Path>>(reader: R, target_dir: P) {
     if let Err(err) = fs::remove_dir_all(&target_dir) {
         eprintln!("{}: failed to remove target directory; output may be dirty: {}", "warning".yellow(), err);
     }
+
+    // top-level entries are written straight into it
+    fs::create_dir_all(target_dir.as_ref()).expect("Failed to create directory");
     
     for (path, action) in actions {
         match action {
             Action::CreateDir => fs::create_dir_all(path).expect("Failed to create directory"),
-            Action::Extract { hash, .. } => {
+            Action::Extract { hash } => {
                 extract_map.insert(PathBuf::from_str(&format!("{:032x}", hash)).unwrap(), path);
             },
-            Action::Download { display_name, url, sha512 } => {
+            Action::Download { display_name, url, hash } => {
                 let client = client.clone();
+                let download_limit = download_limit.clone();
                 join_handles.push(tokio::spawn(async move {
+                    let _permit = download_limit.acquire().await.expect("Download limit was closed");
+
                     #[derive(Debug)]
                     struct PhonyError;
 
@@ -619,9 +1362,10 @@ pub async fn expand<R : Read, P : AsRef<Path>>(reader: R, target_dir: P) {
 
                     println!("{:>12} [{}] {} -> {} (url: {})", "GET".magenta(), "start".magenta(), display_name, path.to_str().unwrap(), &url);
                     
-                    let bytes = cached::download(&url[..], || async {
-                        let response = client.get(&url).send().await
-                            .expect(&format!("Failed to GET {}", &url));
+                    let sha512 = hash.as_ref().and_then(FileHash::sha512);
+                    let bytes = cached::download(&url[..], sha512.as_ref(), || async {
+                        let response = github::download_request(&client, &url).send().await
+                            .unwrap_or_else(|err| panic!("Failed to GET {}: {err}", &url));
 
                         match response.status() {
                             StatusCode::OK => {
@@ -637,55 +1381,67 @@ pub async fn expand<R : Read, P : AsRef<Path>>(reader: R, target_dir: P) {
                             StatusCode::NOT_FOUND => {
                                 println!("{:>12} [{}] {} -> {} (url: {})", "GET".magenta(), "FAILED".red(), display_name, path.to_str().unwrap(), &url);
                                 eprintln!("GET {} was not found", &url);
-                                return Err::<Vec<u8>, Box<dyn std::error::Error>>(Box::new(PhonyError));
+                                Err::<Vec<u8>, Box<dyn std::error::Error>>(Box::new(PhonyError))
                             },
                             status => {
                                 println!("{:>12} [{}] {} -> {} (url: {})", "GET".magenta(), "FAILED".red(), display_name, path.to_str().unwrap(), &url);
                                 eprintln!("GET {} returned random status code {}", &url, status);
-                                return Err::<Vec<u8>, Box<dyn std::error::Error>>(Box::new(PhonyError));
+                                Err::<Vec<u8>, Box<dyn std::error::Error>>(Box::new(PhonyError))
                             }
                         }
                     }).await;
 
-                    let Ok((cache_state, bytes)) = bytes else {
-                        return false
+                    let (cache_state, bytes) = match bytes {
+                        Ok(result) => result,
+                        Err(err) => {
+                            // the download itself already said what went wrong
+                            if !err.is::<PhonyError>() {
+                                println!("{:>12} [{}] {} -> {} (url: {})", "GET".magenta(), "FAILED".red(), display_name, path.to_str().unwrap(), &url);
+                                eprintln!("{}", err);
+                            }
+
+                            return false
+                        }
                     };
 
-                    if let CacheState::Miss { bytes_downloaded, hash } = cache_state {
-                        println!("{:>12} (downloaded {} bytes as {:016x})", "Cache Miss".magenta(), bytes_downloaded, hash);
-                    }
+                    let contents = match cache_state {
+                        CacheState::Hit { hash } => {
+                            println!("{:>12} {} (cached as {:016x})", "Cache Hit".blue(), display_name, hash);
+                            hash
+                        },
+                        CacheState::Miss { bytes_downloaded, hash } => {
+                            println!("{:>12} (downloaded {} bytes as {:016x})", "Cache Miss".magenta(), bytes_downloaded, hash);
+                            hash
+                        }
+                    };
                     
                     let bytes: Vec<u8> = bytes.bytes()
                         .map(|r| r.unwrap_or_else(|e| panic!("Data failed to read: {}", e)))
                         .collect();
                     
-                    if let Some(sha512) = sha512 {
-                        let mut sha512_downloaded = Sha512::new();
-                        sha512_downloaded.update(&bytes);
-                        let result = sha512_downloaded.finalize();
-
-                        if result[..] != sha512 {
+                    if let Some(hash) = &hash {
+                        if !hash.matches(&bytes) {
                             println!("{:>12} [{}] {} -> {} (url: {})", "GET".magenta(), "FAILED".red(), display_name, path.to_str().unwrap(), &url);
-                            eprintln!("File {} failed SHA-512 check (downloaded: {:?}, expected: {:?})", &url, &result[..], &sha512);
+                            eprintln!("File {} failed {} check (downloaded: {}, expected: {})", &url, hash.algorithm(), hash.digest(&bytes), hash.hex());
                             return false;
-                        } 
+                        }
                     }
                     
-                    fs::write(&path, bytes)
-                        .expect(&format!("Failed to write file {}", path.to_str().unwrap()));
+                    let linked = link_from_cache(link_mode, contents, &path);
+                    if !linked {
+                        fs::write(&path, bytes)
+                            .unwrap_or_else(|err| panic!("Failed to write file {}: {err}", path.to_str().unwrap()));
+                    }
 
-                    println!("{:>12} [{}] {} -> {} (url: {})", "GET".magenta(), "success".green(), display_name, path.to_str().unwrap(), &url);
+                    println!("{:>12} [{}] {} -> {} (url: {}){}", "GET".magenta(), "success".green(), display_name, path.to_str().unwrap(), &url,
+                        if linked { format!(" [{:?}]", link_mode).to_lowercase() } else { String::new() });
                     
                     true
                 }))
             },
-            Action::Symlink { source } => {
-                match symlink::symlink_file(source, path) {
-                    Ok(()) => {},
-                    Err(err) => {
-                        eprintln!("{}: failed to symlink server.jar: {}", "error".red(), err);
-                    },
-                };
+            Action::ManualDownload { display_name, page_url } => {
+                println!("{:>12} {} -> {}", "Manual".yellow(), display_name, path.to_str().unwrap());
+                manual_downloads.push((path, display_name, page_url));
             },
             Action::RunScriptTemplate { source, options } => {
                 if let Err(err) = fs::write(&path, parse_template(source, &options)) {
@@ -713,9 +1469,9 @@ pub async fn expand<R : Read, P : AsRef<Path>>(reader: R, target_dir: P) {
             
             let mut contents = Vec::new();
             entry.read_to_end(&mut contents)
-                .expect(&format!("Failed to read from archive file {:?}", entry.path().unwrap()));
+                .unwrap_or_else(|err| panic!("Failed to read from archive file {:?}: {err}", entry.path().unwrap()));
             fs::write(target, contents)
-                .expect(&format!("Failed to write to file {:?}", target));
+                .unwrap_or_else(|err| panic!("Failed to write to file {:?}: {err}", target));
             
             extract_map.remove(&path_buf);
         }
@@ -746,6 +1502,14 @@ pub async fn expand<R : Read, P : AsRef<Path>>(reader: R, target_dir: P) {
                     }
                     
                     if entry.header().entry_type().is_file() {
+                        // the path may be hard linked into the cache, which must not be written through
+                        if let Err(err) = fs::remove_file(&target_path) {
+                            if err.kind() != ErrorKind::NotFound {
+                                error_unpersist(err, persist);
+                                return;
+                            }
+                        }
+
                         if let Err(err) = fs::write(target_path, &buf) {
                             error_unpersist(err, persist);
                             return;
@@ -784,6 +1548,15 @@ pub async fn expand<R : Read, P : AsRef<Path>>(reader: R, target_dir: P) {
         }
     }
     
+    for (path, display_name, page_url) in &manual_downloads {
+        eprintln!("{}: the authors of {} do not allow other programs to download it; download it from {} and save it as {}",
+            "warning".yellow(), display_name, page_url, path.to_str().unwrap());
+    }
+
+    if !manual_downloads.is_empty() {
+        eprintln!("Unpacked target is incomplete until the files above are downloaded by hand");
+    }
+
     if extract_errors {
         eprintln!("Extract errors are present (your jetpacked archive is probably corrupt)");
     }
@@ -796,6 +1569,38 @@ fn error_unpersist(err: std::io::Error, persist: tar::Archive<&mut fs::File>) {
     let file = persist.into_inner();
     file.seek(SeekFrom::Start(0)).expect("Failed to restore persisted files: seek failed");
     file.read_to_end(&mut buf).expect("Failed to restore persisted files: read failed");
-    fs::write(&name, &buf).expect(&format!("Failed to restore persisted files: write to {} failed", &name));
+    fs::write(&name, &buf).unwrap_or_else(|err| panic!("Failed to restore persisted files: write to {} failed: {err}", &name));
     eprintln!("{} saved to {}", "archive of all persisted files".bold(), name);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modrinth::VersionFileHashes;
+
+    fn file(filename: &str, primary: bool) -> VersionFile {
+        VersionFile {
+            hashes: VersionFileHashes { sha512: String::new(), sha1: String::new() },
+            url: format!("https://cdn.modrinth.com/{}", filename),
+            filename: filename.to_string(),
+            primary,
+            size: 0,
+            file_type: None
+        }
+    }
+
+    #[test]
+    fn primary_file_is_named_after_the_project() {
+        assert_eq!(modrinth_file_name("lithium", "0.12.0", &file("lithium-fabric-mc1.20.2-0.12.0.jar", true), false), "lithium-0.12.0.jar");
+    }
+
+    #[test]
+    fn other_files_keep_their_names() {
+        assert_eq!(modrinth_file_name("lithium", "0.12.0", &file("lithium-extra.jar", false), false), "lithium-extra.jar");
+    }
+
+    #[test]
+    fn keep_filename() {
+        assert_eq!(modrinth_file_name("lithium", "0.12.0", &file("lithium-fabric.jar", true), true), "lithium-fabric.jar");
+    }
+}
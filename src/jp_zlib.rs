@@ -1,16 +1,16 @@
-use std::{io::{Write, Read}, path::Path};
+use std::{collections::HashMap, io::{Write, Read}, path::Path};
 
 use libflate::zlib::{Encoder, Decoder};
 
-use crate::jp::{self, SourceManifest};
+use crate::jp::{self, SourceManifest, Manifest, LinkMode};
 
-pub const EXTENSION: &'static str = "jpz";
+pub const EXTENSION: &str = "jpz";
 
-pub async fn pack<W : Write, P1 : AsRef<Path>, P2 : AsRef<Path>>(writer: W, manifest_path: Option<P1>, manifest: SourceManifest, source_dir: P2) {
+pub async fn pack<W : Write, P1 : AsRef<Path>, P2 : AsRef<Path>>(writer: W, manifest_path: Option<P1>, manifest: SourceManifest, source_dir: P2, strict: bool) {
     let mut encoder = Encoder::new(writer)
         .expect("Failed to setup ZLIB encoder");
     
-    jp::pack(&mut encoder, manifest_path, manifest, source_dir).await;
+    jp::pack(&mut encoder, manifest_path, manifest, source_dir, strict).await;
     
     encoder.finish().into_result()
         .expect("Failed to finish ZLIB encoding");
@@ -30,9 +30,23 @@ pub fn unpack_selective<R : Read>(reader: R, name: &str) -> Option<Vec<u8>> {
     jp::unpack_selective(decoder, name)
 }
 
-pub async fn expand<R : Read, P : AsRef<Path>>(reader: R, target_dir: P) {
+pub async fn expand<R : Read, P : AsRef<Path>>(reader: R, target_dir: P, link_mode: LinkMode) {
     let decoder = Decoder::new(reader)
         .expect("Failed to setup ZLIB decoder");
     
-    jp::expand(decoder, target_dir).await;
+    jp::expand(decoder, target_dir, link_mode).await;
+}
+
+pub fn read_manifest<R : Read>(reader: R) -> Option<Manifest> {
+    let decoder = Decoder::new(reader)
+        .expect("Failed to setup ZLIB decoder");
+
+    jp::read_manifest(decoder)
+}
+
+pub fn read_archive<R : Read>(reader: R) -> Option<(Manifest, HashMap<u128, Vec<u8>>)> {
+    let decoder = Decoder::new(reader)
+        .expect("Failed to setup ZLIB decoder");
+
+    jp::read_archive(decoder)
 }
@@ -1,30 +1,45 @@
 mod jp;
 mod jp_zlib;
 mod modrinth;
+mod maven;
+mod github;
+mod curseforge;
+mod pattern;
+mod update;
+mod import;
+mod mrpack;
+mod packwiz;
+mod cfpack;
+mod init;
 mod cached;
+mod config;
+mod serve;
 
-use std::{path::{PathBuf, Path}, fs};
+use std::{collections::HashMap, path::{PathBuf, Path}, fs, net::SocketAddr, time::{SystemTime, UNIX_EPOCH}};
 use std::io::{Read, stdin, stdout, Write};
 
-use clap::{Parser, Subcommand, ValueEnum};
+use clap::{Parser, Subcommand};
 use colored::Colorize;
-use jp::SourceManifest;
+use jp::{SourceManifest, Manifest, LinkMode};
 use syntect::{parsing::SyntaxSet, highlighting::{ThemeSet, Style}, easy::HighlightLines, util::{LinesWithEndings, as_24_bit_terminal_escaped}};
-use crate::cached::cache_dir;
+use crate::cached::{cache_dir, needs_cache_emulation, CacheIndex, PruneOptions};
+use crate::config::{CONFIG, Compression, Overrides};
 
 #[derive(Parser)]
 #[command(about, author, version)]
 struct Cli {
+    /// Use this cache directory instead of the configured one (also JET_CACHE_DIR)
+    #[arg(long, global = true)]
+    cache_dir: Option<PathBuf>,
+
+    /// Ask this `jet cache serve` instance for files before downloading them (e.g. http://host:7878)
+    #[arg(long, global = true)]
+    cache_upstream: Option<String>,
+
     #[command(subcommand)]
     subcommand: SubCommand
 }
 
-#[derive(ValueEnum, Clone)]
-enum Compression {
-    None,
-    Zlib
-}
-
 #[derive(Clone, Subcommand)]
 enum SubCommand {
     Pack {
@@ -37,8 +52,13 @@ enum SubCommand {
         #[arg(short = 'F', long)]
         jetfuel_path: Option<PathBuf>,
         
-        #[arg(short = 'c', long, default_value = "zlib")]
-        compression: Compression
+        /// [default: zlib, or `compression` from the config file]
+        #[arg(short = 'c', long)]
+        compression: Option<Compression>,
+
+        /// Fail instead of warning when a Modrinth project does not support servers or the pack's game version
+        #[arg(long)]
+        strict: bool
     },
     Unpack {
         #[arg(short, long)]
@@ -63,18 +83,164 @@ enum SubCommand {
         output: PathBuf,
 
         #[arg(short = 'c', long, default_value = "zlib")]
-        compression: Option<Compression>
+        compression: Option<Compression>,
+
+        /// How to place downloaded files; hardlink and reflink share the cache's copy and fall back to copy
+        #[arg(short, long, default_value = "copy")]
+        link: LinkMode
+    },
+    /// Bump pinned Modrinth versions in jetfuel.xml to the newest compatible ones
+    Update {
+        #[arg(default_value = ".")]
+        source: PathBuf,
+
+        #[arg(short = 'F', long)]
+        jetfuel_path: Option<PathBuf>,
+
+        /// Apply every update without asking
+        #[arg(short, long)]
+        all: bool,
+
+        /// Only update these projects (as written in jetfuel.xml)
+        #[arg(long, num_args = 1..)]
+        only: Vec<String>,
+
+        /// Show available updates without changing anything
+        #[arg(short = 'n', long)]
+        dry_run: bool
+    },
+    /// Write a jetfuel.xml for an existing server directory
+    Init {
+        #[arg(default_value = ".")]
+        server_dir: PathBuf
+    },
+    /// Turn a modpack from another format into a jetfuel.xml
+    Import {
+        #[command(subcommand)]
+        sub_command: ImportSubCommand
+    },
+    /// Convert a jet archive into a modpack for another format
+    Export {
+        #[command(subcommand)]
+        sub_command: ExportSubCommand
     },
     Cache {
         #[command(subcommand)]
         sub_command: CacheSubCommand
+    },
+    Config {
+        #[command(subcommand)]
+        sub_command: ConfigSubCommand
+    }
+}
+
+#[derive(Clone, Subcommand)]
+enum ImportSubCommand {
+    /// Import a Modrinth modpack (.mrpack)
+    Mrpack {
+        file: PathBuf,
+
+        /// Directory to write jetfuel.xml and the pack's embedded files to
+        #[arg(short, long, default_value = ".")]
+        output: PathBuf
+    },
+    /// Import a packwiz pack
+    Packwiz {
+        /// The pack's pack.toml
+        #[arg(default_value = "pack.toml")]
+        pack: PathBuf,
+
+        /// Directory to write jetfuel.xml and the pack's embedded files to
+        #[arg(short, long, default_value = ".")]
+        output: PathBuf
+    },
+    /// Import a CurseForge modpack zip
+    Curseforge {
+        file: PathBuf,
+
+        /// Directory to write jetfuel.xml and the pack's embedded files to
+        #[arg(short, long, default_value = ".")]
+        output: PathBuf
     }
 }
 
+#[derive(Clone, Subcommand)]
+enum ExportSubCommand {
+    /// Export as a Modrinth modpack (.mrpack) for players
+    Mrpack {
+        file: PathBuf,
+
+        #[arg(short, long)]
+        output: PathBuf,
+
+        #[arg(short = 'c', long)]
+        compression: Option<Compression>
+    },
+    /// Export as a packwiz pack
+    Packwiz {
+        file: PathBuf,
+
+        /// Directory to write pack.toml, index.toml and the pack's files to
+        #[arg(short, long)]
+        output: PathBuf,
+
+        #[arg(short = 'c', long)]
+        compression: Option<Compression>
+    }
+}
+
+#[derive(Clone, Subcommand)]
+enum ConfigSubCommand {
+    /// Print the effective settings after merging the config file, environment and options
+    Show
+}
+
 #[derive(Clone, Subcommand)]
 enum CacheSubCommand {
     Clear,
-    Show
+    Show,
+    List {
+        /// Only list URLs containing this text
+        #[arg(short, long)]
+        url: Option<String>,
+
+        /// Only list URLs downloaded by this archive
+        #[arg(short, long)]
+        archive: Option<PathBuf>,
+
+        #[arg(short = 'c', long)]
+        compression: Option<Compression>
+    },
+    Prune {
+        /// Evict least recently used contents until the cache is at most this size (e.g. 10G)
+        #[arg(long, value_parser = cached::parse_size)]
+        max_size: Option<u64>,
+
+        /// Evict contents not used within this long (e.g. 30d)
+        #[arg(long, value_parser = cached::parse_duration)]
+        older_than: Option<u64>,
+
+        /// Never evict anything downloaded by these archives
+        #[arg(long, num_args = 1..)]
+        keep_referenced_by: Vec<PathBuf>
+    },
+    Verify {
+        /// Move broken files into the cache's quarantine directory instead of deleting them
+        #[arg(short, long)]
+        quarantine: bool
+    },
+    Serve {
+        #[arg(short, long, default_value = "127.0.0.1:7878")]
+        bind: SocketAddr,
+
+        /// Download (and keep) requested URLs that are not cached yet, instead of only serving the cache
+        #[arg(long)]
+        fetch: bool,
+
+        /// URL prefixes --fetch may download from, instead of the hosts jet downloads from itself (mirrors in the config are always allowed)
+        #[arg(long, requires = "fetch")]
+        fetch_allow: Vec<String>
+    }
 }
 
 fn canonicalize_dir(path: PathBuf) -> PathBuf {
@@ -84,13 +250,65 @@ fn canonicalize_dir(path: PathBuf) -> PathBuf {
 
 #[tokio::main]
 async fn main() {
-    match Cli::parse().subcommand {
+    let cli = Cli::parse();
+
+    config::set_overrides(Overrides {
+        cache_dir: cli.cache_dir,
+        cache_upstream: cli.cache_upstream
+    });
+    // report a broken config file before doing any work
+    once_cell::sync::Lazy::force(&CONFIG);
+
+    match cli.subcommand {
         SubCommand::Pack {
             source,
             output,
             jetfuel_path,
-            compression
-        } => perform_pack(output, jetfuel_path, source, compression).await,
+            compression,
+            strict
+        } => {
+            let compression = compression.or(CONFIG.compression).unwrap_or(Compression::Zlib);
+
+            perform_pack(output, jetfuel_path, source, compression, strict).await;
+            auto_prune_cache();
+        },
+
+        SubCommand::Update {
+            source,
+            jetfuel_path,
+            all,
+            only,
+            dry_run
+        } => {
+            let jetfuel_path = jetfuel_path.unwrap_or_else(|| source.join("jetfuel.xml"));
+            update::update(jetfuel_path, update::UpdateOptions { all, only, dry_run }).await;
+        },
+
+        SubCommand::Init { server_dir } => {
+            init::init(server_dir).await;
+        },
+
+        SubCommand::Import { sub_command: ImportSubCommand::Mrpack { file, output } } => {
+            mrpack::import(file, output).await;
+        },
+
+        SubCommand::Import { sub_command: ImportSubCommand::Packwiz { pack, output } } => {
+            packwiz::import(pack, output).await;
+        },
+
+        SubCommand::Import { sub_command: ImportSubCommand::Curseforge { file, output } } => {
+            cfpack::import(file, output).await;
+        },
+
+        SubCommand::Export { sub_command: ExportSubCommand::Mrpack { file, output, compression } } => {
+            let (manifest, embedded) = read_archive(&file, compression);
+            mrpack::export(manifest, embedded, output).await;
+        },
+
+        SubCommand::Export { sub_command: ExportSubCommand::Packwiz { file, output, compression } } => {
+            let (manifest, embedded) = read_archive(&file, compression);
+            packwiz::export(manifest, embedded, output).await;
+        },
 
         SubCommand::Unpack {
             source,
@@ -106,11 +324,80 @@ async fn main() {
         SubCommand::Expand {
             source,
             output,
-            compression
-        } => perform_expand(source, canonicalize_dir(output), compression).await,
+            compression,
+            link
+        } => {
+            perform_expand(source, canonicalize_dir(output), compression, link).await;
+            auto_prune_cache();
+        },
 
         SubCommand::Cache { sub_command: CacheSubCommand::Show } => {
             println!("Jet cache directory is {}", cache_dir().to_str().unwrap());
+
+            if !cached::uses_symlinks(&CacheIndex::load()) {
+                println!("URL mappings are kept in the cache index (no symlinks)");
+            }
+
+            if needs_cache_emulation() {
+                println!("{}: no user cache directory exists; each run caches into a new temporary directory, which is left behind", "note".blue());
+            }
+        }
+
+        SubCommand::Cache { sub_command: CacheSubCommand::List { url, archive, compression } } => {
+            perform_cache_list(url, archive, compression)
+        }
+
+        SubCommand::Cache { sub_command: CacheSubCommand::Prune { max_size, older_than, keep_referenced_by } } => {
+            if max_size.is_none() && older_than.is_none() {
+                eprintln!("{}: nothing to do; pass --max-size and/or --older-than", "error".red());
+                return;
+            }
+
+            let keep_urls = keep_referenced_by.iter()
+                .flat_map(|archive| read_archive_manifest(archive, None).download_urls())
+                .collect();
+
+            perform_cache_prune(&PruneOptions { max_size, older_than, keep_urls });
+        }
+
+        SubCommand::Cache { sub_command: CacheSubCommand::Verify { quarantine } } => {
+            let report = cached::verify(quarantine);
+
+            println!("{:>12} {} files; {} broken ({}), {} dangling links and {} stale index entries removed",
+                "Verified".green(),
+                report.files_checked,
+                report.files_broken,
+                if quarantine { "quarantined" } else { "removed" },
+                report.links_removed,
+                report.entries_removed);
+        }
+
+        SubCommand::Cache { sub_command: CacheSubCommand::Serve { bind, fetch, fetch_allow } } => {
+            serve::serve(bind, serve::FetchOptions { enabled: fetch, allow: fetch_allow }).await
+        }
+
+        SubCommand::Config { sub_command: ConfigSubCommand::Show } => {
+            match config::config_path() {
+                Some(path) if path.exists() => println!("# read from {}", path.to_str().unwrap()),
+                Some(path) => println!("# {} does not exist; showing defaults", path.to_str().unwrap()),
+                None => println!("# no config directory; showing defaults")
+            }
+
+            let mut effective = toml::Table::try_from(&*CONFIG).expect("Failed to serialize config");
+            if let Some(toml::Value::Table(cache)) = effective.get_mut("cache") {
+                cache.insert("dir".into(), cache_dir().to_str().unwrap().into());
+            }
+
+            // only say whether secrets are set
+            for (section, key) in [("github", "token"), ("curseforge", "api-key")] {
+                if let Some(toml::Value::Table(section)) = effective.get_mut(section) {
+                    if let Some(value) = section.get_mut(key) {
+                        *value = "********".into();
+                    }
+                }
+            }
+
+            print!("{}", toml::to_string_pretty(&effective).expect("Failed to serialize config"));
         }
 
         SubCommand::Cache { sub_command: CacheSubCommand::Clear } => {
@@ -157,28 +444,28 @@ fn parse_compression<P : AsRef<Path>>(compression: Option<Compression>, source:
     })
 }
 
-async fn perform_pack(output: PathBuf, jetfuel_path: Option<PathBuf>, source: PathBuf, compression: Compression) {
+async fn perform_pack(output: PathBuf, jetfuel_path: Option<PathBuf>, source: PathBuf, compression: Compression, strict: bool) {
     let mut writer = fs::File::create(&output)
-        .expect(&format!("Failed to create file: {:?}", &output));
+        .unwrap_or_else(|err| panic!("Failed to create file: {:?}: {err}", &output));
     let jetfuel_path = jetfuel_path.unwrap_or_else(|| source.join("jetfuel.xml"));
             
     let jetfuel_reader = std::io::BufReader::new(
         fs::File::open(&jetfuel_path)
-            .expect(&format!("Failed to open path: {:?} (does it exist?)", &jetfuel_path))
+            .unwrap_or_else(|err| panic!("Failed to open path: {:?} (does it exist?): {err}", &jetfuel_path))
     );
             
     let jetfuel: SourceManifest = quick_xml::de::from_reader(jetfuel_reader)
-        .expect(&format!("Failed to read contents of {:?}", jetfuel_path));
+        .unwrap_or_else(|err| panic!("Failed to read contents of {:?}: {err}", jetfuel_path));
             
     match compression {
-        Compression::None => jp::pack(&mut writer, Some(jetfuel_path), jetfuel, source).await,
-        Compression::Zlib => jp_zlib::pack(&mut writer, Some(jetfuel_path), jetfuel, source).await,
+        Compression::None => jp::pack(&mut writer, Some(jetfuel_path), jetfuel, source, strict).await,
+        Compression::Zlib => jp_zlib::pack(&mut writer, Some(jetfuel_path), jetfuel, source, strict).await,
     }
 }
 
 fn perform_unpack(source: PathBuf, output: PathBuf, compression: Option<Compression>) {
     let reader = std::fs::File::open(&source)
-                .expect(&format!("Failed to open file: {:?}", &source));
+                .unwrap_or_else(|err| panic!("Failed to open file: {:?}: {err}", &source));
 
     match parse_compression(compression, &source) {
         Compression::None => jp::unpack(reader, output),
@@ -194,7 +481,7 @@ async fn perform_peek(source: PathBuf, compression: Option<Compression>) {
     let mut h = HighlightLines::new(syntax, &ts.themes["base16-ocean.dark"]);
     
     let reader = std::fs::File::open(&source)
-                .expect(&format!("Failed to open file: {:?}", &source));
+                .unwrap_or_else(|err| panic!("Failed to open file: {:?}: {err}", &source));
 
     let contents = match parse_compression(compression, &source) {
         Compression::None => jp::unpack_selective(reader, "@jetfuel.xml"),
@@ -223,12 +510,109 @@ async fn perform_peek(source: PathBuf, compression: Option<Compression>) {
     }
 }
 
-async fn perform_expand(source: PathBuf, output: PathBuf, compression: Option<Compression>) {
+async fn perform_expand(source: PathBuf, output: PathBuf, compression: Option<Compression>, link_mode: LinkMode) {
     let reader = std::fs::File::open(&source)
-                .expect(&format!("Failed to open file: {:?}", &source));
+                .unwrap_or_else(|err| panic!("Failed to open file: {:?}: {err}", &source));
 
     match parse_compression(compression, &source) {
-        Compression::None => jp::expand(reader, output).await,
-        Compression::Zlib => jp_zlib::expand(reader, output).await
+        Compression::None => jp::expand(reader, output, link_mode).await,
+        Compression::Zlib => jp_zlib::expand(reader, output, link_mode).await
+    }
+}
+
+fn read_archive_manifest(source: &Path, compression: Option<Compression>) -> Manifest {
+    let reader = std::fs::File::open(source)
+                .unwrap_or_else(|err| panic!("Failed to open file: {:?}: {err}", source));
+
+    let manifest = match parse_compression(compression, source) {
+        Compression::None => jp::read_manifest(reader),
+        Compression::Zlib => jp_zlib::read_manifest(reader)
+    };
+
+    manifest.unwrap_or_else(|| panic!("No readable @manifest in {:?}", source))
+}
+
+fn read_archive(source: &Path, compression: Option<Compression>) -> (Manifest, HashMap<u128, Vec<u8>>) {
+    let reader = std::fs::File::open(source)
+                .unwrap_or_else(|err| panic!("Failed to open file: {:?}: {err}", source));
+
+    let archive = match parse_compression(compression, source) {
+        Compression::None => jp::read_archive(reader),
+        Compression::Zlib => jp_zlib::read_archive(reader)
+    };
+
+    archive.unwrap_or_else(|| panic!("No readable @manifest in {:?}", source))
+}
+
+fn format_size(bytes: usize) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
     }
+
+    if unit == 0 {
+        format!("{} {}", bytes, UNITS[0])
+    } else {
+        format!("{:.1} {}", size, UNITS[unit])
+    }
+}
+
+fn format_age(timestamp: u64) -> String {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+    let secs = now.saturating_sub(timestamp);
+
+    match secs {
+        0..=59 => format!("{}s ago", secs),
+        60..=3599 => format!("{}m ago", secs / 60),
+        3600..=86399 => format!("{}h ago", secs / 3600),
+        _ => format!("{}d ago", secs / 86400)
+    }
+}
+
+fn perform_cache_list(url: Option<String>, archive: Option<PathBuf>, compression: Option<Compression>) {
+    let archive_urls = archive.map(|archive| read_archive_manifest(&archive, compression).download_urls());
+    let index = CacheIndex::load();
+    
+    let mut entries: Vec<_> = index.entries.iter()
+        .filter(|(entry_url, _)| url.as_ref().is_none_or(|filter| entry_url.contains(filter.as_str())))
+        .filter(|(entry_url, _)| archive_urls.as_ref().is_none_or(|urls| urls.contains(entry_url)))
+        .collect();
+    entries.sort_by_key(|(_, entry)| std::cmp::Reverse(entry.last_used));
+
+    let mut total = 0;
+
+    for (entry_url, entry) in &entries {
+        total += entry.size;
+        println!("{:016x} {:>10}  fetched {:>8}  used {:>8}  {}",
+            entry.contents,
+            format_size(entry.size),
+            format_age(entry.fetched),
+            format_age(entry.last_used),
+            entry_url.blue());
+    }
+
+    println!("{:>12} {} entries ({})", "Total".green(), entries.len(), format_size(total));
+}
+
+fn perform_cache_prune(options: &PruneOptions) {
+    let report = cached::prune(options);
+
+    println!("{:>12} {} files ({}), {} URLs; {} remaining",
+        "Pruned".green(),
+        report.files_removed,
+        format_size(report.bytes_freed as usize),
+        report.urls_removed,
+        format_size(report.bytes_kept as usize));
+}
+
+fn auto_prune_cache() {
+    let Some(max_size) = &CONFIG.cache.max_size else {
+        return;
+    };
+
+    perform_cache_prune(&PruneOptions { max_size: Some(max_size.bytes), ..Default::default() });
 }
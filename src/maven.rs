@@ -0,0 +1,160 @@
+// resolving artifacts in maven repositories
+//
+// artifacts are pinned at pack time: SNAPSHOT versions are resolved to the
+// timestamped build they currently point at, and the hash comes from the
+// checksum files published next to every artifact
+
+use colored::Colorize;
+use reqwest::StatusCode;
+use serde::Deserialize;
+
+use crate::{config::CONFIG, jp::{FileHash, self}};
+
+#[derive(Deserialize, Debug)]
+struct Metadata {
+    versioning: Option<Versioning>
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct Versioning {
+    snapshot: Option<Snapshot>,
+    snapshot_versions: Option<SnapshotVersions>
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct Snapshot {
+    timestamp: Option<String>,
+    build_number: Option<String>
+}
+
+#[derive(Deserialize, Debug)]
+struct SnapshotVersions {
+    #[serde(rename = "snapshotVersion", default)]
+    entries: Vec<SnapshotVersion>
+}
+
+#[derive(Deserialize, Debug)]
+struct SnapshotVersion {
+    classifier: Option<String>,
+    extension: String,
+    value: String
+}
+
+pub struct Coordinates<'a> {
+    pub repo: &'a str,
+    pub group: &'a str,
+    pub artifact: &'a str,
+    pub version: &'a str,
+    pub classifier: Option<&'a str>
+}
+
+pub struct Resolved {
+    pub url: String,
+    pub hash: FileHash
+}
+
+const EXTENSION: &str = "jar";
+
+impl Coordinates<'_> {
+    fn version_url(&self) -> String {
+        format!("{}/{}/{}/{}",
+            self.repo.trim_end_matches('/'),
+            self.group.replace('.', "/"),
+            self.artifact,
+            self.version)
+    }
+
+    /// The file name this artifact is usually saved as, e.g. `artifact-1.0-SNAPSHOT-sources.jar`.
+    pub fn file_name(&self) -> String {
+        self.file_name_for(self.version)
+    }
+
+    fn file_name_for(&self, version: &str) -> String {
+        match self.classifier {
+            Some(classifier) => format!("{}-{}-{}.{}", self.artifact, version, classifier, EXTENSION),
+            None => format!("{}-{}.{}", self.artifact, version, EXTENSION)
+        }
+    }
+
+    /// Finds the URL of the file and its hash.
+    pub async fn resolve(&self, client: &reqwest::Client) -> Resolved {
+        let version = if self.version.ends_with("-SNAPSHOT") {
+            let version = self.resolve_snapshot(client).await;
+            println!("{:>12} {}:{}:{} -> {}", "Resolved".green(), self.group, self.artifact, self.version, version);
+            version
+        } else {
+            self.version.to_string()
+        };
+
+        let url = format!("{}/{}", self.version_url(), self.file_name_for(&version));
+
+        let hash = match fetch_checksum(client, &url).await {
+            Some(hash) => hash,
+            None => {
+                eprintln!("{}: {} has no checksum file; pinning what it serves now", "warning".yellow(), url);
+                jp::pin_sha512(client, &url).await.0
+            }
+        };
+
+        Resolved { url, hash }
+    }
+
+    async fn resolve_snapshot(&self, client: &reqwest::Client) -> String {
+        let url = format!("{}/maven-metadata.xml", self.version_url());
+        let text = fetch_text(client, &url).await
+            .unwrap_or_else(|| panic!("{} does not exist, so {} cannot be resolved", url, self.version));
+        let metadata: Metadata = quick_xml::de::from_str(&text)
+            .unwrap_or_else(|err| panic!("Failed to read {}: {err}", url));
+        let versioning = metadata.versioning
+            .unwrap_or_else(|| panic!("{} does not list any versions", url));
+
+        let listed = versioning.snapshot_versions.iter()
+            .flat_map(|versions| &versions.entries)
+            .find(|entry| entry.extension == EXTENSION && entry.classifier.as_deref() == self.classifier);
+
+        if let Some(entry) = listed {
+            return entry.value.clone();
+        }
+
+        // older repositories only record the latest build
+        match versioning.snapshot {
+            Some(Snapshot { timestamp: Some(timestamp), build_number: Some(build_number) }) => {
+                self.version.replace("SNAPSHOT", &format!("{}-{}", timestamp, build_number))
+            },
+            _ => panic!("{} does not say which build {} is", url, self.version)
+        }
+    }
+}
+
+/// Reads the strongest checksum file published next to `url`, if there is one.
+async fn fetch_checksum(client: &reqwest::Client, url: &str) -> Option<FileHash> {
+    for (extension, hash) in [
+        ("sha512", FileHash::Sha512 as fn(String) -> FileHash),
+        ("sha256", FileHash::Sha256),
+        ("sha1", FileHash::Sha1)
+    ] {
+        let Some(text) = fetch_text(client, &format!("{}.{}", url, extension)).await else {
+            continue;
+        };
+
+        // some repositories append the file name after the hash
+        let hex = text.split_whitespace().next().unwrap_or_default().to_ascii_lowercase();
+        return Some(hash(hex).validated());
+    }
+
+    None
+}
+
+async fn fetch_text(client: &reqwest::Client, url: &str) -> Option<String> {
+    let response = client.get(CONFIG.mirrored(url)).send().await
+        .unwrap_or_else(|err| panic!("Failed to GET {}: {err}", url));
+
+    match response.status() {
+        StatusCode::OK => Some(response.text().await
+            .unwrap_or_else(|err| panic!("Failed to read {}: {err}", url))),
+        StatusCode::NOT_FOUND => None,
+        status => panic!("GET {} returned random status code {}", url, status)
+    }
+}
@@ -1,20 +1,26 @@
 // simple and small modrinth api stuff
 
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
 use colored::Colorize;
-use reqwest::StatusCode;
-use serde::{Deserialize, Serialize};
+use once_cell::sync::Lazy;
+use reqwest::{StatusCode, Url, header::{CONTENT_TYPE, ETAG, IF_NONE_MATCH}};
+use tokio::{sync::Mutex, time::Instant};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
 
-use crate::cached::CacheState;
+use crate::{cached::{CacheState, CachePolicy, Fetched}, pattern};
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
 #[serde(rename_all = "kebab-case")]
 pub enum SideSupport {
     Required,
     Optional,
-    Unsupported
+    Unsupported,
+    /// Not stated by the project; treated like `Optional`.
+    Unknown
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Serialize, Debug)]
 #[serde(rename_all = "kebab-case")]
 pub enum ApprovalStatus {
     Approved,
@@ -29,14 +35,14 @@ pub enum ApprovalStatus {
     Unknown
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Serialize, Debug)]
 pub struct DonationUrl {
     pub id: String,
     pub platform: String,
     pub url: String
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Serialize, Debug)]
 #[serde(rename_all = "kebab-case")]
 pub enum ProjectType {
     Mod,
@@ -47,28 +53,28 @@ pub enum ProjectType {
     Shader
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Serialize, Debug)]
 #[serde(rename_all = "kebab-case")]
 pub enum MonetizationStatus {
     Monetized,
     Demonetized,
-    ForceDemonitized
+    ForceDemonetized
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Serialize, Debug)]
 pub struct ModeratorMessage {
     pub message: String,
     pub body: Option<String>
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Serialize, Debug)]
 pub struct License {
     pub id: String,
     pub name: String,
-    pub url: String
+    pub url: Option<String>
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Serialize, Debug)]
 pub struct GalleryEntry {
     pub url: String,
     pub featured: bool,
@@ -78,7 +84,7 @@ pub struct GalleryEntry {
     pub ordering: usize
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Serialize, Debug)]
 pub struct ProjectGetResponse {
     pub slug: String,
     pub title: String,
@@ -117,7 +123,7 @@ pub struct ProjectGetResponse {
     pub gallery: Option<Vec<GalleryEntry>>
 }
 
-#[derive(Deserialize, Serialize, Debug)]
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
 #[serde(rename_all = "kebab-case")]
 pub enum DependencyType {
     Required,
@@ -126,7 +132,7 @@ pub enum DependencyType {
     Embedded
 }
 
-#[derive(Deserialize, Serialize, Debug)]
+#[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct DependencyEntry {
     pub version_id: Option<String>,
     pub project_id: Option<String>,
@@ -134,7 +140,8 @@ pub struct DependencyEntry {
     pub dependency_type: DependencyType
 }
 
-#[derive(Deserialize, Serialize, Debug)]
+/// Ordered from most to least stable.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 #[serde(rename_all = "kebab-case")]
 pub enum VersionType {
     Release,
@@ -152,7 +159,10 @@ pub struct VersionFileHashes {
 #[serde(rename_all = "kebab-case")]
 pub enum VersionFileType {
     RequiredResourcePack,
-    OptionalResourcePack
+    OptionalResourcePack,
+    /// Anything else, e.g. sources or javadoc jars.
+    #[serde(other)]
+    Unknown
 }
 
 #[derive(Deserialize, Serialize, Debug, PartialEq, Eq)]
@@ -181,7 +191,7 @@ pub struct ProjectVersionGetResponse {
     pub name: String,
     pub version_number: String,
     pub changelog: Option<String>,
-    pub dependenices: Option<Vec<DependencyEntry>>,
+    pub dependencies: Option<Vec<DependencyEntry>>,
     pub game_versions: Vec<String>,
     pub version_type: VersionType,
     pub loaders: Vec<String>,
@@ -197,39 +207,344 @@ pub struct ProjectVersionGetResponse {
     pub files: Vec<VersionFile>
 }
 
-pub async fn project_version_get(
+/// Version IDs always name the same version, but its status can still change
+/// (e.g. to archived), so they are re-checked daily. Version numbers are looked
+/// up every time, as a project can replace the version behind one.
+const VERSION_ID_TTL: u64 = 24 * 60 * 60;
+
+/// Bumped whenever the cached form of responses changes, so that entries
+/// cached in an older form are not read back.
+const CACHE_FORMAT: u32 = 2;
+
+const API_URL: &str = "https://api.modrinth.com/v2";
+
+/// How many IDs are sent in one bulk request, to keep URLs reasonably short.
+const BULK_CHUNK: usize = 100;
+
+/// Requests are held back until this instant once Modrinth says the rate limit
+/// is used up.
+static RATE_LIMITED_UNTIL: Lazy<Mutex<Option<Instant>>> = Lazy::new(|| Mutex::new(None));
+
+/// How many times a rate limited request is retried before giving up.
+const RATE_LIMIT_RETRIES: u32 = 5;
+
+/// Version IDs are 8 random base62 characters. Version numbers can look the
+/// same (e.g. `20231018`), so an ID also needs a letter; an ID made only of
+/// digits is still found, just like a version number would be.
+pub fn is_version_id(version: &str) -> bool {
+    version.len() == 8
+        && version.chars().all(|c| c.is_ascii_alphanumeric())
+        && version.chars().any(|c| c.is_ascii_alphabetic())
+}
+
+async fn api_get<T : DeserializeOwned + Serialize>(
     client: &reqwest::Client,
-    project: &str,
-    version: &str
-) -> ProjectVersionGetResponse {
-    let url = format!("https://api.modrinth.com/v2/project/{}/version/{}", project, version);
-    let (cache_state, bytes) = crate::cached::download(&url.clone()[..], move || async move {
-        let response = client.get(url)
-                    .send().await
-                    .expect(&format!("Failed to GET version info of {} {}", project, version));
+    url: String,
+    policy: CachePolicy,
+    what: &str
+) -> T {
+    let cache_key = format!("{}#jet-cache-format-{}", url, CACHE_FORMAT);
+
+    let (cache_state, bytes) = crate::cached::download_with_policy(&cache_key[..], policy, None, move |etag| async move {
+        let mut request = client.get(url);
+        if let Some(etag) = etag {
+            request = request.header(IF_NONE_MATCH, etag);
+        }
+
+        let response = send(request, what).await;
 
         match response.status() {
             StatusCode::OK => {
-                let bytes: Vec<u8> = response.bytes().await.expect("Could not read bytes from Modrinth version request").into();
-                let response = serde_json::from_slice::<ProjectVersionGetResponse>(&bytes[..])
-                    .expect("Failed to deserialize ProjectVersionGetResponse");
+                let etag = response.headers().get(ETAG)
+                    .and_then(|etag| etag.to_str().ok())
+                    .map(String::from);
+                let bytes: Vec<u8> = response.bytes().await.expect("Could not read bytes from Modrinth request").into();
+                let response = serde_json::from_slice::<T>(&bytes[..])
+                    .unwrap_or_else(|err| panic!("Failed to deserialize {}: {err}", what));
 
                 let mut real_bytes = Vec::new();
                 ciborium::into_writer(&response, &mut real_bytes)
-                    .expect("Failed to serialize ProjectVersionGetResponse");
-                Ok(real_bytes)
+                    .unwrap_or_else(|err| panic!("Failed to serialize {}: {err}", what));
+                Ok(Fetched::Data { bytes: real_bytes, etag })
             },
+            StatusCode::NOT_MODIFIED => Ok(Fetched::NotModified),
             StatusCode::NOT_FOUND => {
-                panic!("Unknown Modrinth version {} {}", project, version);
+                panic!("Unknown Modrinth {}", what);
             },
-            status => panic!("Random status code getting Modrinth version {} {}: {:?}", project, version, status)
+            status => panic!("Random status code getting Modrinth {}: {:?}", what, status)
         }
-    }).await.expect("Failed to get Modrinth version info");
+    }).await.unwrap_or_else(|err| panic!("Failed to get Modrinth {}: {err}", what));
 
     if let CacheState::Miss { bytes_downloaded, hash } = cache_state {
         println!("{:>12} (downloaded {} bytes as {:016x})", "Cache Miss".magenta(), bytes_downloaded, hash);
     }
 
     ciborium::from_reader(&bytes[..])
-        .expect("Failed to deserialize ProjectVersionGetResponse")
+        .unwrap_or_else(|err| panic!("Failed to deserialize {}: {err}", what))
+}
+
+/// Sends `request`, waiting out Modrinth's rate limit (`X-Ratelimit-*`) when
+/// it is used up. Each retry waits at least twice as long as the one before,
+/// and after [`RATE_LIMIT_RETRIES`] of them the request fails.
+async fn send(request: reqwest::RequestBuilder, what: &str) -> reqwest::Response {
+    let mut attempt = 0;
+    loop {
+        let until = *RATE_LIMITED_UNTIL.lock().await;
+        if let Some(until) = until {
+            tokio::time::sleep_until(until).await;
+        }
+
+        let response = request.try_clone().expect("Modrinth requests have no streamed bodies")
+            .send().await
+            .unwrap_or_else(|err| panic!("Failed to request {}: {err}", what));
+
+        let header = |name: &str| response.headers().get(name)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse::<u64>().ok());
+        let remaining = header("x-ratelimit-remaining");
+        let reset = header("x-ratelimit-reset").unwrap_or(1);
+
+        let limited = response.status() == StatusCode::TOO_MANY_REQUESTS;
+        if limited && attempt == RATE_LIMIT_RETRIES {
+            panic!("Modrinth kept rate limiting requests for {} after {} retries", what, RATE_LIMIT_RETRIES);
+        }
+
+        if limited || remaining == Some(0) {
+            let wait = if limited { reset.max(1 << attempt) } else { reset };
+            println!("{:>12} Modrinth rate limit reached; waiting {}s", "Waiting".yellow(), wait);
+            *RATE_LIMITED_UNTIL.lock().await = Some(Instant::now() + Duration::from_secs(wait));
+        }
+
+        if !limited {
+            return response;
+        }
+
+        attempt += 1;
+    }
+}
+
+/// Gets everything with the given `ids` from a bulk endpoint (`/versions`,
+/// `/projects`). Anything that does not exist is left out.
+async fn bulk_get<T : DeserializeOwned>(client: &reqwest::Client, endpoint: &str, ids: &[String], what: &str) -> Vec<T> {
+    let mut results = vec![];
+
+    for chunk in ids.chunks(BULK_CHUNK) {
+        let mut url = Url::parse(&format!("{}/{}", API_URL, endpoint)).expect("Modrinth API URL is valid");
+        url.query_pairs_mut().append_pair("ids", &serde_json::json!(chunk).to_string());
+
+        let response = send(client.get(url), what).await;
+        match response.status() {
+            StatusCode::OK => {
+                let bytes = response.bytes().await.expect("Could not read bytes from Modrinth request");
+                results.extend(serde_json::from_slice::<Vec<T>>(&bytes[..])
+                    .unwrap_or_else(|err| panic!("Failed to deserialize {}: {err}", what)));
+            },
+            status => panic!("Random status code getting Modrinth {}: {:?}", what, status)
+        }
+    }
+
+    println!("{:>12} {} {} [bulk]", "GET".magenta(), results.len(), what);
+    results
+}
+
+pub async fn versions_get(client: &reqwest::Client, ids: &[String]) -> Vec<ProjectVersionGetResponse> {
+    bulk_get(client, "versions", ids, "versions").await
+}
+
+/// `ids` may also be slugs.
+pub async fn projects_get(client: &reqwest::Client, ids: &[String]) -> Vec<ProjectGetResponse> {
+    bulk_get(client, "projects", ids, "projects").await
+}
+
+/// Finds the versions that files with the given SHA-512 hashes belong to,
+/// keyed by hash. Files Modrinth does not know are left out.
+pub async fn version_files_get(client: &reqwest::Client, sha512s: &[String]) -> HashMap<String, ProjectVersionGetResponse> {
+    let mut results = HashMap::new();
+
+    for chunk in sha512s.chunks(BULK_CHUNK) {
+        let body = serde_json::json!({ "hashes": chunk, "algorithm": "sha512" });
+        let request = client.post(format!("{}/version_files", API_URL))
+            .header(CONTENT_TYPE, "application/json")
+            .body(body.to_string());
+
+        let response = send(request, "versions by hash").await;
+        match response.status() {
+            StatusCode::OK => {
+                let bytes = response.bytes().await.expect("Could not read bytes from Modrinth request");
+                results.extend(serde_json::from_slice::<HashMap<String, ProjectVersionGetResponse>>(&bytes[..])
+                    .unwrap_or_else(|err| panic!("Failed to deserialize versions by hash: {err}")));
+            },
+            status => panic!("Random status code getting Modrinth versions by hash: {:?}", status)
+        }
+    }
+
+    println!("{:>12} {} versions by hash [bulk]", "POST".magenta(), results.len());
+    results
+}
+
+/// Gets projects named by ID or slug, keyed by both, so they can be looked up
+/// the way they were named.
+pub async fn projects_by_reference(client: &reqwest::Client, references: &[String]) -> HashMap<String, Arc<ProjectGetResponse>> {
+    let mut projects = HashMap::new();
+
+    for project in projects_get(client, references).await {
+        let project = Arc::new(project);
+        projects.insert(project.id.clone(), project.clone());
+        projects.insert(project.slug.clone(), project);
+    }
+
+    projects
+}
+
+pub async fn project_get(client: &reqwest::Client, project: &str) -> ProjectGetResponse {
+    api_get(
+        client,
+        format!("{}/project/{}", API_URL, project),
+        CachePolicy::Revalidate,
+        &format!("project {}", project)
+    ).await
+}
+
+pub async fn project_version_get(
+    client: &reqwest::Client,
+    project: &str,
+    version: &str
+) -> ProjectVersionGetResponse {
+    let policy = if is_version_id(version) {
+        CachePolicy::Ttl(VERSION_ID_TTL)
+    } else {
+        CachePolicy::Revalidate
+    };
+
+    api_get(
+        client,
+        format!("{}/project/{}/version/{}", API_URL, project, version),
+        policy,
+        &format!("version {} {}", project, version)
+    ).await
+}
+
+pub async fn version_get(client: &reqwest::Client, version_id: &str) -> ProjectVersionGetResponse {
+    api_get(
+        client,
+        format!("{}/version/{}", API_URL, version_id),
+        CachePolicy::Ttl(VERSION_ID_TTL),
+        &format!("version {}", version_id)
+    ).await
+}
+
+/// Versions of `project` for `game_version` and `loader`, newest first.
+pub async fn project_versions_get(
+    client: &reqwest::Client,
+    project: &str,
+    game_version: &str,
+    loader: &str
+) -> Vec<ProjectVersionGetResponse> {
+    let mut url = Url::parse(&format!("{}/project/{}/version", API_URL, project))
+        .unwrap_or_else(|err| panic!("Invalid Modrinth project {}: {err}", project));
+    url.query_pairs_mut()
+        .append_pair("game_versions", &serde_json::json!([game_version]).to_string())
+        .append_pair("loaders", &serde_json::json!([loader]).to_string());
+
+    api_get(
+        client,
+        url.to_string(),
+        CachePolicy::Revalidate,
+        &format!("versions of {} for {} {}", project, loader, game_version)
+    ).await
+}
+
+/// The most recently published of `versions` that is at least as stable as `channel`.
+pub fn newest_version(versions: Vec<ProjectVersionGetResponse>, channel: VersionType) -> Option<ProjectVersionGetResponse> {
+    versions.into_iter()
+        .filter(|version| version.version_type <= channel)
+        .max_by(|a, b| a.date_published.cmp(&b.date_published))
+}
+
+/// The file Modrinth treats as the main one: the one marked primary, or the
+/// first if none is.
+pub fn primary_file(files: &[VersionFile]) -> Option<&VersionFile> {
+    files.iter().find(|file| file.primary).or(files.first())
+}
+
+/// Files matching `name_pattern` and `file_type`, or just the primary file if
+/// neither is given. That file is marked primary even if Modrinth picked it as
+/// the first, so it is named like one. Other files of unknown types (sources
+/// jars and such) are never picked.
+pub fn select_files(files: Vec<VersionFile>, name_pattern: Option<&str>, file_type: Option<&VersionFileType>) -> Vec<VersionFile> {
+    if name_pattern.is_none() && file_type.is_none() {
+        let primary = files.iter().position(|file| file.primary).unwrap_or(0);
+        return files.into_iter().nth(primary)
+            .map(|file| VersionFile { primary: true, ..file })
+            .into_iter().collect();
+    }
+
+    files.into_iter()
+        .filter(|file| file.primary || file.file_type != Some(VersionFileType::Unknown))
+        .filter(|file| name_pattern.is_none_or(|name_pattern| pattern::matches(name_pattern, &file.filename)))
+        .filter(|file| file_type.is_none_or(|file_type| file.file_type.as_ref() == Some(file_type)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn version_ids() {
+        assert!(is_version_id("IIJJKKLL"));
+        assert!(is_version_id("a1b2c3d4"));
+        assert!(!is_version_id("20231018"));
+        assert!(!is_version_id("1.20.2-1"));
+        assert!(!is_version_id("abc"));
+    }
+
+    fn file(filename: &str, primary: bool, file_type: Option<VersionFileType>) -> VersionFile {
+        VersionFile {
+            hashes: VersionFileHashes { sha512: String::new(), sha1: String::new() },
+            url: format!("https://cdn.modrinth.com/{}", filename),
+            filename: filename.to_string(),
+            primary,
+            size: 0,
+            file_type
+        }
+    }
+
+    fn names(files: &[VersionFile]) -> Vec<&str> {
+        files.iter().map(|file| &file.filename[..]).collect()
+    }
+
+    #[test]
+    fn primary_file_by_default() {
+        let files = vec![file("a-sources.jar", false, Some(VersionFileType::Unknown)), file("a.jar", true, None)];
+        assert_eq!(names(&select_files(files, None, None)), ["a.jar"]);
+    }
+
+    #[test]
+    fn first_file_becomes_primary() {
+        let selected = select_files(vec![file("a.jar", false, None), file("b.jar", false, None)], None, None);
+        assert_eq!(names(&selected), ["a.jar"]);
+        assert!(selected[0].primary);
+    }
+
+    #[test]
+    fn files_by_name_pattern() {
+        let files = vec![
+            file("a.jar", true, None),
+            file("a-extra.jar", false, None),
+            file("a-sources.jar", false, Some(VersionFileType::Unknown)),
+            file("a.zip", false, None)
+        ];
+        assert_eq!(names(&select_files(files, Some("a*.jar"), None)), ["a.jar", "a-extra.jar"]);
+    }
+
+    #[test]
+    fn files_by_type() {
+        let files = vec![
+            file("a.jar", true, None),
+            file("pack.zip", false, Some(VersionFileType::RequiredResourcePack)),
+            file("extra.zip", false, Some(VersionFileType::OptionalResourcePack))
+        ];
+        assert_eq!(names(&select_files(files, None, Some(&VersionFileType::RequiredResourcePack))), ["pack.zip"]);
+    }
 }
@@ -0,0 +1,362 @@
+// modrinth modpacks (.mrpack): importing them as jetfuel.xml, and exporting
+// jet archives as them
+//
+// an .mrpack is a zip with a modrinth.index.json listing files to download,
+// plus overrides/ and server-overrides/ directories copied over the result
+
+use std::{collections::{HashMap, HashSet}, fs::File, io::Write, path::Path};
+
+use colored::Colorize;
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use sha1::Sha1;
+use sha2::{Digest, Sha512};
+use zip::{CompressionMethod, ZipWriter, write::FileOptions};
+
+use crate::{cached, config::CONFIG, import::{self, Element, Jetfuel}, jp::{self, Entry, FileHash, Manifest, ProjectInfo}, modrinth::{self, SideSupport, VersionFile}};
+
+const INDEX_NAME: &str = "modrinth.index.json";
+const OVERRIDES: &str = "overrides/";
+const SERVER_OVERRIDES: &str = "server-overrides/";
+
+/// Hosts launchers are allowed to download the files of a pack from.
+const ALLOWED_HOSTS: [&str; 4] = ["cdn.modrinth.com", "github.com", "raw.githubusercontent.com", "gitlab.com"];
+
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct Index {
+    format_version: u32,
+    game: String,
+    version_id: String,
+    name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    summary: Option<String>,
+    files: Vec<IndexFile>,
+    dependencies: HashMap<String, String>
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+struct IndexHashes {
+    sha1: String,
+    sha512: String
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+struct Env {
+    client: SideSupport,
+    server: SideSupport
+}
+
+impl Env {
+    /// .mrpack has no `unknown`, so that is written as `optional`.
+    fn of(client: SideSupport, server: SideSupport) -> Self {
+        let known = |side| match side {
+            SideSupport::Unknown => SideSupport::Optional,
+            side => side
+        };
+
+        Self { client: known(client), server: known(server) }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct IndexFile {
+    path: String,
+    hashes: IndexHashes,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    env: Option<Env>,
+    downloads: Vec<String>,
+    file_size: usize
+}
+
+/// Turns the `.mrpack` at `file` into a jetfuel.xml, and the files it embeds,
+/// in `output_dir`.
+pub async fn import<P1 : AsRef<Path>, P2 : AsRef<Path>>(file: P1, output_dir: P2) {
+    let file = file.as_ref();
+    let output_dir = output_dir.as_ref();
+
+    let mut archive = import::open_zip(file);
+    let index: Index = import::read_zip_json(&mut archive, file, INDEX_NAME);
+
+    if index.format_version != 1 || index.game != "minecraft" {
+        panic!("Unsupported modpack: format {} for {}", index.format_version, index.game);
+    }
+
+    import::prepare_output(output_dir);
+
+    let client = jp::http_client();
+    let mut jetfuel = Jetfuel::new(ProjectInfo {
+        name: index.name.clone(),
+        description: index.summary.clone().unwrap_or_default(),
+        version: index.version_id.clone(),
+        authors: vec![]
+    });
+
+    for loader in ["forge", "neoforge", "quilt-loader"] {
+        if index.dependencies.contains_key(loader) {
+            import::warn_unsupported_loader(loader);
+        }
+    }
+
+    let minecraft = index.dependencies.get("minecraft");
+    let fabric_loader = index.dependencies.get("fabric-loader");
+    if let Some(server) = import::fabric_server(&client, minecraft, fabric_loader).await {
+        jetfuel.add(Path::new(""), server);
+    }
+
+    let files: Vec<_> = index.files.iter()
+        .filter(|file| {
+            let server_side = file.env.as_ref().map(|env| &env.server);
+            if matches!(server_side, Some(SideSupport::Unsupported)) {
+                println!("{:>12} {} (client only)", "Skipped".yellow(), file.path);
+                return false;
+            }
+
+            true
+        })
+        .collect();
+
+    let sha512s: Vec<_> = files.iter().map(|file| file.hashes.sha512.to_ascii_lowercase()).collect();
+    let versions = modrinth::version_files_get(&client, &sha512s).await;
+
+    let project_ids: Vec<_> = versions.values()
+        .map(|version| version.project_id.clone())
+        .collect::<HashSet<_>>()
+        .into_iter()
+        .collect();
+    let slugs: HashMap<_, _> = modrinth::projects_get(&client, &project_ids).await.into_iter()
+        .map(|project| (project.id, project.slug))
+        .collect();
+
+    let mut from_modrinth = 0;
+
+    for (file, sha512) in files.iter().zip(&sha512s) {
+        let path = import::relative_path(&file.path);
+        let name = path.file_name().and_then(|name| name.to_str()).expect("Imported paths are UTF-8");
+        let dir = path.parent().unwrap_or(Path::new(""));
+
+        let found = versions.get(sha512).and_then(|version| {
+            version.files.iter()
+                .find(|version_file| &version_file.hashes.sha512 == sha512)
+                .map(|version_file| (version, version_file))
+        });
+
+        let element = match found {
+            Some((version, version_file)) if import::can_keep_name(version_file, name) => {
+                from_modrinth += 1;
+
+                let project = slugs.get(&version.project_id).unwrap_or(&version.project_id);
+                import::modrinth_element(project, version, version_file)
+            },
+            _ => {
+                let url = file.downloads.first()
+                    .unwrap_or_else(|| panic!("{} has no download URL", file.path));
+
+                Element::new("download")
+                    .attribute("url", url)
+                    .attribute("name", name)
+                    .attribute("sha512", sha512)
+            }
+        };
+
+        jetfuel.add(dir, element);
+    }
+
+    // server overrides win over overrides with the same path
+    let overrides = import::extract_overrides(&mut archive, &[OVERRIDES, SERVER_OVERRIDES], output_dir, &mut jetfuel);
+
+    jetfuel.write(output_dir);
+
+    println!("{:>12} {} {}: {} Modrinth project(s), {} download(s), {} embedded file(s) into {}",
+        "Imported".green(),
+        index.name,
+        index.version_id,
+        from_modrinth,
+        files.len() - from_modrinth,
+        overrides,
+        output_dir.to_str().unwrap());
+}
+
+/// A file of an exported pack, before anything is downloaded.
+enum Planned<'a> {
+    Modrinth { path: String, project: &'a str, file: &'a VersionFile },
+    Download { path: String, url: &'a str, hash: &'a FileHash },
+    Override { path: String, hash: u128 }
+}
+
+fn join(dir: &str, name: &str) -> String {
+    if dir.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}/{}", dir, name)
+    }
+}
+
+/// Works out what `entry` becomes in the pack, recording anything a `.mrpack`
+/// cannot represent in `problems`.
+fn plan<'a>(entry: &'a Entry, dir: &str, planned: &mut Vec<Planned<'a>>, dependencies: &mut HashMap<String, String>, problems: &mut Vec<String>) {
+    match entry {
+        Entry::Directory { name, contents } => {
+            for child in contents {
+                plan(child, &join(dir, name), planned, dependencies, problems);
+            }
+        },
+        Entry::File { name, hash, .. } => {
+            planned.push(Planned::Override { path: join(dir, name), hash: *hash });
+        },
+        Entry::Modrinth { project, version, files, keep_filename, .. } => {
+            for file in files {
+                let path = join(dir, &jp::modrinth_file_name(project, version, file, *keep_filename));
+                planned.push(Planned::Modrinth { path, project, file });
+            }
+        },
+        Entry::Download { name, url, hash } => {
+            let host = Url::parse(url).ok().and_then(|url| url.host_str().map(String::from));
+
+            if host.is_some_and(|host| ALLOWED_HOSTS.contains(&&host[..])) {
+                planned.push(Planned::Download { path: join(dir, name), url, hash });
+            } else {
+                problems.push(format!("{}: {} is not on a host launchers download from ({})", join(dir, name), url, ALLOWED_HOSTS.join(", ")));
+            }
+        },
+        Entry::CurseForge { name, .. } => {
+            problems.push(format!("{}: CurseForge files cannot be listed in a .mrpack", join(dir, name)));
+        },
+        Entry::FabricServerJar { minecraft_version, loader_version, .. } => {
+            dependencies.insert("minecraft".into(), minecraft_version.clone());
+            dependencies.insert("fabric-loader".into(), loader_version.clone());
+        },
+        Entry::RunScript { name, .. } => {
+            println!("{:>12} {} (run scripts are only for servers)", "Skipped".yellow(), join(dir, name));
+        },
+        Entry::Persist { .. } => {} // only matters to `jet expand`
+    }
+}
+
+/// Downloads `url` through the cache, checking it against its pinned hash.
+async fn download(client: &reqwest::Client, url: &str, hash: &FileHash) -> Vec<u8> {
+    let (_, bytes) = cached::download(url, hash.sha512().as_ref(), || async {
+        let response = client.get(CONFIG.mirrored(url)).send().await?.error_for_status()?;
+        Ok(response.bytes().await?.into())
+    }).await.unwrap_or_else(|err| panic!("Failed to download {}: {err}", url));
+
+    if !hash.matches(&bytes) {
+        panic!("{} does not match its pinned {} hash", url, hash.algorithm());
+    }
+
+    bytes
+}
+
+/// Writes the archive with `manifest` and `embedded` files as a `.mrpack` at `output`.
+pub async fn export<P : AsRef<Path>>(manifest: Manifest, embedded: HashMap<u128, Vec<u8>>, output: P) {
+    let output = output.as_ref();
+
+    let mut planned = vec![];
+    let mut dependencies = HashMap::new();
+    let mut problems = vec![];
+
+    for entry in &manifest.contents {
+        plan(entry, "", &mut planned, &mut dependencies, &mut problems);
+    }
+
+    if !dependencies.contains_key("minecraft") {
+        problems.push("the archive has no <fabric-server>, so the pack's Minecraft version is unknown".into());
+    }
+
+    if !problems.is_empty() {
+        for problem in &problems {
+            eprintln!("{}: {}", "error".red(), problem);
+        }
+
+        panic!("{} part(s) of the archive cannot be represented in a .mrpack", problems.len());
+    }
+
+    let client = jp::http_client();
+
+    let projects: Vec<_> = planned.iter()
+        .filter_map(|planned| match planned {
+            Planned::Modrinth { project, .. } => Some(project.to_string()),
+            _ => None
+        })
+        .collect::<HashSet<_>>()
+        .into_iter()
+        .collect();
+
+    let projects = modrinth::projects_by_reference(&client, &projects).await;
+
+    let mut files = vec![];
+    let mut overrides = vec![];
+
+    for planned in planned {
+        match planned {
+            Planned::Modrinth { path, project, file } => {
+                let project = projects.get(project)
+                    .unwrap_or_else(|| panic!("Unknown Modrinth project {}", project));
+
+                files.push(IndexFile {
+                    path,
+                    hashes: IndexHashes { sha1: file.hashes.sha1.clone(), sha512: file.hashes.sha512.clone() },
+                    env: Some(Env::of(project.client_side, project.server_side)),
+                    downloads: vec![file.url.clone()],
+                    file_size: file.size
+                });
+            },
+            Planned::Download { path, url, hash } => {
+                let bytes = download(&client, url, hash).await;
+
+                files.push(IndexFile {
+                    path,
+                    hashes: IndexHashes {
+                        sha1: hex::encode(Sha1::digest(&bytes)),
+                        sha512: hex::encode(Sha512::digest(&bytes))
+                    },
+                    env: None,
+                    downloads: vec![url.to_string()],
+                    file_size: bytes.len()
+                });
+            },
+            Planned::Override { path, hash } => {
+                let data = embedded.get(&hash)
+                    .unwrap_or_else(|| panic!("{} ({:032x}) is missing from the archive", path, hash));
+                overrides.push((path, data));
+            }
+        }
+    }
+
+    let description = manifest.project_info.description.trim();
+    let index = Index {
+        format_version: 1,
+        game: "minecraft".into(),
+        version_id: manifest.project_info.version.clone(),
+        name: manifest.project_info.name.clone(),
+        summary: (!description.is_empty()).then(|| description.to_string()),
+        files,
+        dependencies
+    };
+
+    let mut zip = ZipWriter::new(File::create(output).unwrap_or_else(|err| panic!("Failed to create {:?}: {err}", output)));
+    let options = FileOptions::default().compression_method(CompressionMethod::Deflated);
+
+    let index_bytes = serde_json::to_vec_pretty(&index).expect("Failed to serialize modrinth.index.json");
+    zip.start_file(INDEX_NAME, options)
+        .and_then(|_| Ok(zip.write_all(&index_bytes)?))
+        .unwrap_or_else(|err| panic!("Failed to write {}: {err}", INDEX_NAME));
+
+    for (path, data) in &overrides {
+        let name = format!("{}{}", OVERRIDES, path);
+        zip.start_file(&name[..], options)
+            .and_then(|_| Ok(zip.write_all(data)?))
+            .unwrap_or_else(|err| panic!("Failed to write {}: {err}", name));
+    }
+
+    zip.finish().unwrap_or_else(|err| panic!("Failed to finish {:?}: {err}", output));
+
+    println!("{:>12} {} {}: {} file(s) to download, {} override(s) into {}",
+        "Exported".green(),
+        index.name,
+        index.version_id,
+        index.files.len(),
+        overrides.len(),
+        output.to_str().unwrap());
+}
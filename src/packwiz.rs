@@ -0,0 +1,520 @@
+// packwiz packs: importing them as jetfuel.xml, and exporting jet archives as
+// them
+//
+// a packwiz pack is a pack.toml pointing at an index.toml, which lists every
+// file of the pack; mods are listed as .pw.toml metafiles saying where to
+// download them from
+
+use std::{collections::{BTreeMap, HashMap, HashSet}, fs, path::{Path, PathBuf}};
+
+use colored::Colorize;
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use sha2::{Digest, Sha256};
+
+use crate::{curseforge, import::{self, Element, Jetfuel}, jp::{self, Entry, FileHash, Manifest, ProjectInfo}, modrinth::{self, SideSupport, VersionFile}};
+
+const PACK_FORMAT: &str = "packwiz:1.1.0";
+const INDEX_NAME: &str = "index.toml";
+const METAFILE_EXTENSION: &str = ".pw.toml";
+
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(rename_all = "kebab-case")]
+struct Pack {
+    name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    author: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    version: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    description: Option<String>,
+    pack_format: String,
+    index: PackIndex,
+    versions: BTreeMap<String, String>
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(rename_all = "kebab-case")]
+struct PackIndex {
+    file: String,
+    hash_format: String,
+    hash: String
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(rename_all = "kebab-case")]
+struct Index {
+    hash_format: String,
+    #[serde(default)]
+    files: Vec<IndexFile>
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(rename_all = "kebab-case")]
+struct IndexFile {
+    file: String,
+    hash: String,
+    /// Defaults to the index's hash format.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    hash_format: Option<String>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    metafile: bool
+}
+
+#[derive(Deserialize, Serialize, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+enum Side {
+    Client,
+    Server,
+    #[default] Both
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(rename_all = "kebab-case")]
+struct MetaFile {
+    name: String,
+    filename: String,
+    #[serde(default)]
+    side: Side,
+    download: MetaDownload,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    update: Option<MetaUpdate>
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(rename_all = "kebab-case")]
+struct MetaDownload {
+    /// Missing for CurseForge files, which are looked up by ID instead.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    url: Option<String>,
+    hash_format: String,
+    hash: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    mode: Option<String>
+}
+
+#[derive(Deserialize, Serialize, Debug, Default)]
+struct MetaUpdate {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    modrinth: Option<ModrinthUpdate>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    curseforge: Option<CurseForgeUpdate>
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(rename_all = "kebab-case")]
+struct ModrinthUpdate {
+    mod_id: String,
+    version: String
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(rename_all = "kebab-case")]
+struct CurseForgeUpdate {
+    file_id: u32,
+    project_id: u32
+}
+
+fn read_toml<T : DeserializeOwned>(path: &Path) -> T {
+    let text = fs::read_to_string(path)
+        .unwrap_or_else(|err| panic!("Failed to read {:?}: {err}", path));
+    toml::from_str(&text)
+        .unwrap_or_else(|err| panic!("Failed to read {:?}: {err}", path))
+}
+
+/// The hash packwiz writes as `hash_format`, if jet can check it.
+fn file_hash(hash_format: &str, hash: &str) -> Option<FileHash> {
+    let hash = hash.to_ascii_lowercase();
+
+    match hash_format {
+        "sha1" => Some(FileHash::Sha1(hash)),
+        "sha256" => Some(FileHash::Sha256(hash)),
+        "sha512" => Some(FileHash::Sha512(hash)),
+        _ => None // md5 and murmur2
+    }
+}
+
+fn hash_format(hash: &FileHash) -> &'static str {
+    match hash {
+        FileHash::Sha1(_) => "sha1",
+        FileHash::Sha256(_) => "sha256",
+        FileHash::Sha512(_) => "sha512"
+    }
+}
+
+/// Turns the packwiz pack at `pack_path` (its pack.toml) into a jetfuel.xml,
+/// and the files it embeds, in `output_dir`.
+pub async fn import<P1 : AsRef<Path>, P2 : AsRef<Path>>(pack_path: P1, output_dir: P2) {
+    let pack_path = pack_path.as_ref();
+    let output_dir = output_dir.as_ref();
+
+    let pack: Pack = read_toml(pack_path);
+    let index_path = pack_path.parent().unwrap_or(Path::new("")).join(import::relative_path(&pack.index.file));
+    let index: Index = read_toml(&index_path);
+    // files in the index are relative to it
+    let index_dir = index_path.parent().unwrap_or(Path::new(""));
+
+    import::prepare_output(output_dir);
+
+    let client = jp::http_client();
+    let mut jetfuel = Jetfuel::new(ProjectInfo {
+        name: pack.name.clone(),
+        description: pack.description.clone().unwrap_or_default(),
+        version: pack.version.clone().unwrap_or_default(),
+        authors: pack.author.clone().into_iter().collect()
+    });
+
+    for loader in ["forge", "neoforge", "quilt", "liteloader"] {
+        if pack.versions.contains_key(loader) {
+            import::warn_unsupported_loader(loader);
+        }
+    }
+
+    if let Some(server) = import::fabric_server(&client, pack.versions.get("minecraft"), pack.versions.get("fabric")).await {
+        jetfuel.add(Path::new(""), server);
+    }
+
+    let files: Vec<_> = index.files.iter()
+        .map(|file| {
+            let path = import::relative_path(&file.file);
+            let metafile = file.metafile.then(|| read_toml::<MetaFile>(&index_dir.join(&path)));
+            (file, path, metafile)
+        })
+        .collect();
+
+    let version_ids: Vec<_> = files.iter()
+        .filter_map(|(_, _, metafile)| metafile.as_ref()?.update.as_ref()?.modrinth.as_ref())
+        .map(|update| update.version.clone())
+        .collect::<HashSet<_>>()
+        .into_iter()
+        .collect();
+    let versions: HashMap<_, _> = modrinth::versions_get(&client, &version_ids).await.into_iter()
+        .map(|version| (version.id.clone(), version))
+        .collect();
+
+    let project_ids: Vec<_> = versions.values()
+        .map(|version| version.project_id.clone())
+        .collect::<HashSet<_>>()
+        .into_iter()
+        .collect();
+    let slugs: HashMap<_, _> = modrinth::projects_get(&client, &project_ids).await.into_iter()
+        .map(|project| (project.id, project.slug))
+        .collect();
+
+    let (mut from_modrinth, mut from_curseforge, mut downloads, mut embedded) = (0, 0, 0, 0);
+
+    for (file, path, metafile) in &files {
+        let dir = path.parent().unwrap_or(Path::new(""));
+
+        let Some(metafile) = metafile else {
+            let data = fs::read(index_dir.join(path))
+                .unwrap_or_else(|err| panic!("Failed to read {:?}: {err}", index_dir.join(path)));
+
+            let hash_format = file.hash_format.as_deref().unwrap_or(&index.hash_format);
+            if file_hash(hash_format, &file.hash).is_some_and(|hash| !hash.matches(&data)) {
+                eprintln!("{}: {} does not match its hash in {}; is the index up to date?", "warning".yellow(), file.file, INDEX_NAME);
+            }
+
+            import::write_file(output_dir, path, &data);
+            let name = path.file_name().and_then(|name| name.to_str()).expect("Imported paths are UTF-8");
+            jetfuel.add(dir, Element::new("file").attribute("name", name));
+            embedded += 1;
+            continue;
+        };
+
+        if metafile.side == Side::Client {
+            println!("{:>12} {} (client only)", "Skipped".yellow(), metafile.name);
+            continue;
+        }
+
+        let name = &metafile.filename;
+        if import::relative_path(name).components().count() != 1 {
+            panic!("{} has a filename that is not just a name: {}", file.file, name);
+        }
+
+        let update = metafile.update.as_ref();
+
+        let found = update.and_then(|update| update.modrinth.as_ref())
+            .and_then(|update| versions.get(&update.version))
+            .and_then(|version| {
+                version.files.iter()
+                    .find(|version_file| import::can_keep_name(version_file, name))
+                    .map(|version_file| (version, version_file))
+            });
+
+        let element = if let Some((version, version_file)) = found {
+            from_modrinth += 1;
+            let project = slugs.get(&version.project_id).unwrap_or(&version.project_id);
+            import::modrinth_element(project, version, version_file)
+        } else if let Some(update) = update.and_then(|update| update.curseforge.as_ref()) {
+            from_curseforge += 1;
+            Element::new("curseforge")
+                .attribute("project", update.project_id.to_string())
+                .attribute("file", update.file_id.to_string())
+        } else {
+            let url = metafile.download.url.as_ref()
+                .unwrap_or_else(|| panic!("{} has no download URL", file.file));
+            downloads += 1;
+
+            let element = Element::new("download")
+                .attribute("url", url)
+                .attribute("name", name);
+
+            match file_hash(&metafile.download.hash_format, &metafile.download.hash) {
+                Some(hash) => element.attribute(hash_format(&hash), hash.hex()),
+                None => {
+                    eprintln!("{}: jet cannot check {} hashes, so {} is pinned by what it serves when packed",
+                        "warning".yellow(), metafile.download.hash_format, name);
+                    element
+                }
+            }
+        };
+
+        jetfuel.add(dir, element);
+    }
+
+    jetfuel.write(output_dir);
+
+    println!("{:>12} {}: {} Modrinth project(s), {} CurseForge file(s), {} download(s), {} embedded file(s) into {}",
+        "Imported".green(),
+        pack.name,
+        from_modrinth,
+        from_curseforge,
+        downloads,
+        embedded,
+        output_dir.to_str().unwrap());
+}
+
+/// A file of an exported pack, before anything is looked up.
+enum Planned<'a> {
+    Modrinth { dir: PathBuf, project: &'a str, version_id: &'a str, file: &'a VersionFile, name: String },
+    Download { dir: PathBuf, name: &'a str, url: &'a str, hash: &'a FileHash },
+    CurseForge { dir: PathBuf, project: &'a str, file_id: u32, name: &'a str, hash: &'a FileHash },
+    File { path: PathBuf, hash: u128 }
+}
+
+/// Works out what `entry` becomes in the pack, recording anything packwiz
+/// cannot represent in `problems`.
+fn plan<'a>(entry: &'a Entry, dir: &Path, planned: &mut Vec<Planned<'a>>, versions: &mut BTreeMap<String, String>, problems: &mut Vec<String>) {
+    match entry {
+        Entry::Directory { name, contents } => {
+            for child in contents {
+                plan(child, &dir.join(name), planned, versions, problems);
+            }
+        },
+        Entry::File { name, hash, .. } => {
+            planned.push(Planned::File { path: dir.join(name), hash: *hash });
+        },
+        Entry::Modrinth { project, version, version_id, files, keep_filename } => {
+            // archives packed before versions were pinned by ID only have the number
+            let Some(version_id) = version_id else {
+                problems.push(format!("{:?}: {} {} has no version ID, which packwiz needs; repack the archive", dir, project, version));
+                return;
+            };
+
+            for file in files {
+                let name = jp::modrinth_file_name(project, version, file, *keep_filename);
+                planned.push(Planned::Modrinth { dir: dir.to_path_buf(), project, version_id, file, name });
+            }
+        },
+        Entry::Download { name, url, hash } => {
+            planned.push(Planned::Download { dir: dir.to_path_buf(), name, url, hash });
+        },
+        Entry::CurseForge { project, file_id, name, hash, .. } => match hash {
+            Some(hash) => planned.push(Planned::CurseForge { dir: dir.to_path_buf(), project, file_id: *file_id, name, hash }),
+            None => problems.push(format!("{:?}: packwiz needs a hash for CurseForge files", dir.join(name)))
+        },
+        Entry::FabricServerJar { minecraft_version, loader_version, .. } => {
+            versions.insert("minecraft".into(), minecraft_version.clone());
+            versions.insert("fabric".into(), loader_version.clone());
+        },
+        Entry::RunScript { name, .. } => {
+            println!("{:>12} {} (run scripts are only for servers)", "Skipped".yellow(), dir.join(name).to_str().unwrap());
+        },
+        Entry::Persist { .. } => {} // only matters to `jet expand`
+    }
+}
+
+fn side(client: SideSupport, server: SideSupport) -> Side {
+    match (client, server) {
+        (_, SideSupport::Unsupported) => Side::Client,
+        (SideSupport::Unsupported, _) => Side::Server,
+        _ => Side::Both
+    }
+}
+
+/// Writes `data` to `path` in `output_dir` and lists it in the index.
+fn write_indexed(output_dir: &Path, files: &mut Vec<IndexFile>, path: &Path, data: &[u8], metafile: bool) {
+    import::write_file(output_dir, path, data);
+
+    files.push(IndexFile {
+        file: path.to_str().expect("Paths in archives are UTF-8").replace('\\', "/"),
+        hash: hex::encode(Sha256::digest(data)),
+        hash_format: None,
+        metafile
+    });
+}
+
+/// Writes `metafile` as `<stem>.pw.toml` in `dir`. When another file already
+/// took that name (`foo.jar` next to `foo.zip`), the whole file name is used
+/// instead, with a number added if even that is taken.
+fn write_metafile(output_dir: &Path, files: &mut Vec<IndexFile>, dir: &Path, metafile: MetaFile) {
+    let taken = |path: &Path| files.iter().any(|file| Path::new(&file.file) == path);
+
+    let stem = Path::new(&metafile.filename).file_stem().and_then(|stem| stem.to_str()).unwrap_or(&metafile.filename);
+    let mut path = dir.join(format!("{}{}", stem, METAFILE_EXTENSION));
+    if taken(&path) {
+        path = dir.join(format!("{}{}", metafile.filename, METAFILE_EXTENSION));
+    }
+
+    let mut n = 2;
+    while taken(&path) {
+        path = dir.join(format!("{}-{}{}", metafile.filename, n, METAFILE_EXTENSION));
+        n += 1;
+    }
+
+    let text = toml::to_string(&metafile)
+        .unwrap_or_else(|err| panic!("Failed to serialize {:?}: {err}", path));
+
+    write_indexed(output_dir, files, &path, text.as_bytes(), true);
+}
+
+/// Writes the archive with `manifest` and `embedded` files as a packwiz pack in `output_dir`.
+pub async fn export<P : AsRef<Path>>(manifest: Manifest, embedded: HashMap<u128, Vec<u8>>, output_dir: P) {
+    let output_dir = output_dir.as_ref();
+    let pack_path = output_dir.join("pack.toml");
+
+    if pack_path.exists() {
+        panic!("{:?} already exists; export into another directory", pack_path);
+    }
+
+    let mut planned = vec![];
+    let mut versions = BTreeMap::new();
+    let mut problems = vec![];
+
+    for entry in &manifest.contents {
+        plan(entry, Path::new(""), &mut planned, &mut versions, &mut problems);
+    }
+
+    if !versions.contains_key("minecraft") {
+        problems.push("the archive has no <fabric-server>, so the pack's Minecraft version is unknown".into());
+    }
+
+    if !problems.is_empty() {
+        for problem in &problems {
+            eprintln!("{}: {}", "error".red(), problem);
+        }
+
+        panic!("{} part(s) of the archive cannot be represented in a packwiz pack", problems.len());
+    }
+
+    let client = jp::http_client();
+
+    let projects: Vec<_> = planned.iter()
+        .filter_map(|planned| match planned {
+            Planned::Modrinth { project, .. } => Some(project.to_string()),
+            _ => None
+        })
+        .collect::<HashSet<_>>()
+        .into_iter()
+        .collect();
+    let projects = modrinth::projects_by_reference(&client, &projects).await;
+
+    let mut files = vec![];
+
+    for planned in planned {
+        match planned {
+            Planned::Modrinth { dir, project, version_id, file, name } => {
+                let project = projects.get(project)
+                    .unwrap_or_else(|| panic!("Unknown Modrinth project {}", project));
+
+                write_metafile(output_dir, &mut files, &dir, MetaFile {
+                    name: project.title.clone(),
+                    filename: name,
+                    side: side(project.client_side, project.server_side),
+                    download: MetaDownload {
+                        url: Some(file.url.clone()),
+                        hash_format: "sha512".into(),
+                        hash: file.hashes.sha512.clone(),
+                        mode: None
+                    },
+                    update: Some(MetaUpdate {
+                        modrinth: Some(ModrinthUpdate { mod_id: project.id.clone(), version: version_id.to_string() }),
+                        ..Default::default()
+                    })
+                });
+            },
+            Planned::Download { dir, name, url, hash } => {
+                write_metafile(output_dir, &mut files, &dir, MetaFile {
+                    name: name.to_string(),
+                    filename: name.to_string(),
+                    side: Side::Both,
+                    download: MetaDownload {
+                        url: Some(url.to_string()),
+                        hash_format: hash_format(hash).into(),
+                        hash: hash.hex().to_string(),
+                        mode: None
+                    },
+                    update: None
+                });
+            },
+            Planned::CurseForge { dir, project, file_id, name, hash } => {
+                // archives name projects by slug, but packwiz needs the ID
+                let project = curseforge::mod_get(&client, project).await;
+
+                write_metafile(output_dir, &mut files, &dir, MetaFile {
+                    name: project.name,
+                    filename: name.to_string(),
+                    side: Side::Both,
+                    download: MetaDownload {
+                        url: None,
+                        hash_format: hash_format(hash).into(),
+                        hash: hash.hex().to_string(),
+                        mode: Some("metadata:curseforge".into())
+                    },
+                    update: Some(MetaUpdate {
+                        curseforge: Some(CurseForgeUpdate { file_id, project_id: project.id }),
+                        ..Default::default()
+                    })
+                });
+            },
+            Planned::File { path, hash } => {
+                let data = embedded.get(&hash)
+                    .unwrap_or_else(|| panic!("{:?} ({:032x}) is missing from the archive", path, hash));
+                write_indexed(output_dir, &mut files, &path, data, false);
+            }
+        }
+    }
+
+    files.sort_by(|a, b| a.file.cmp(&b.file));
+    let file_count = files.len();
+
+    let index_text = toml::to_string(&Index { hash_format: "sha256".into(), files })
+        .expect("Failed to serialize index.toml");
+    import::write_file(output_dir, Path::new(INDEX_NAME), index_text.as_bytes());
+
+    let project_info = &manifest.project_info;
+    let description = project_info.description.trim();
+    let pack = Pack {
+        name: project_info.name.clone(),
+        author: (!project_info.authors.is_empty()).then(|| project_info.authors.join(", ")),
+        version: Some(project_info.version.clone()),
+        description: (!description.is_empty()).then(|| description.to_string()),
+        pack_format: PACK_FORMAT.into(),
+        index: PackIndex {
+            file: INDEX_NAME.into(),
+            hash_format: "sha256".into(),
+            hash: hex::encode(Sha256::digest(index_text.as_bytes()))
+        },
+        versions
+    };
+
+    let pack_text = toml::to_string(&pack).expect("Failed to serialize pack.toml");
+    import::write_file(output_dir, Path::new("pack.toml"), pack_text.as_bytes());
+
+    println!("{:>12} {} {}: {} file(s) into {}",
+        "Exported".green(),
+        pack.name,
+        project_info.version,
+        file_count,
+        output_dir.to_str().unwrap());
+}
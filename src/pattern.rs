@@ -0,0 +1,73 @@
+// shell-style wildcards for picking files by name
+//
+//   *  any run of characters, including none
+//   ?  exactly one character
+
+pub fn matches(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+
+    // position after the last `*` and the text position it is trying to match from
+    let mut backtrack = None;
+    let (mut p, mut t) = (0, 0);
+
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p + 1, t));
+                p += 1;
+            },
+            Some(&c) if c == '?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            },
+            _ => match backtrack {
+                Some((star_p, star_t)) => {
+                    // let the last `*` swallow one more character
+                    backtrack = Some((star_p, star_t + 1));
+                    p = star_p;
+                    t = star_t + 1;
+                },
+                None => return false
+            }
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn literal() {
+        assert!(matches("server.jar", "server.jar"));
+        assert!(!matches("server.jar", "server.jar2"));
+        assert!(!matches("server.jar", "server.ja"));
+    }
+
+    #[test]
+    fn star() {
+        assert!(matches("*.jar", "mod.jar"));
+        assert!(matches("*.jar", ".jar"));
+        assert!(matches("mod-*-fabric.jar", "mod-1.2.3-fabric.jar"));
+        assert!(!matches("*.jar", "mod.jar.sha1"));
+        assert!(matches("*", ""));
+    }
+
+    #[test]
+    fn star_backtracks() {
+        // the first `-fabric` is not the one that ends the name
+        assert!(matches("*-fabric.jar", "mod-fabric-api-fabric.jar"));
+        assert!(matches("a*b*c", "aXbYbZc"));
+        assert!(!matches("a*b*c", "aXbYbZ"));
+    }
+
+    #[test]
+    fn question_mark() {
+        assert!(matches("mod-?.jar", "mod-1.jar"));
+        assert!(!matches("mod-?.jar", "mod-.jar"));
+        assert!(!matches("mod-?.jar", "mod-12.jar"));
+    }
+}
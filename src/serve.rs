@@ -0,0 +1,233 @@
+// tiny HTTP server exposing the local cache to other jet instances
+//
+//   GET /url-hash/<hex>  contents cached for the URL with this MeowHash
+//   GET /sha512/<hex>    contents with this SHA-512
+//   GET /url?u=<url>     contents of <url>, downloaded into this cache first if needed
+//                        (with --fetch, and only from allowed URL prefixes)
+
+use std::{collections::HashMap, net::SocketAddr, sync::{Arc, Mutex}, time::SystemTime};
+
+use colored::Colorize;
+use meowhash::MeowHasher;
+use reqwest::{header::{HeaderMap, HeaderValue, USER_AGENT}, StatusCode, Url};
+use tokio::{io::{AsyncReadExt, AsyncWriteExt}, net::{TcpListener, TcpStream}};
+
+use crate::{cached::{self, CacheIndex}, config::CONFIG, jp::USER_AGENT_VALUE};
+
+const MAX_REQUEST_SIZE: usize = 8192;
+
+/// Where jet itself downloads files from, which `--fetch` may fetch when no
+/// `--fetch-allow` is given.
+const DEFAULT_FETCH_ALLOW: [&str; 7] = [
+    "https://cdn.modrinth.com/",
+    "https://github.com/",
+    "https://meta.fabricmc.net/",
+    "https://maven.fabricmc.net/",
+    "https://repo1.maven.org/",
+    "https://edge.forgecdn.net/",
+    "https://mediafilez.forgecdn.net/"
+];
+
+pub struct FetchOptions {
+    pub enabled: bool,
+    /// URL prefixes that may be fetched; [`DEFAULT_FETCH_ALLOW`] if empty.
+    pub allow: Vec<String>
+}
+
+impl FetchOptions {
+    /// Whether `url` may be fetched: it must be http(s) and start with an
+    /// allowed prefix or the `from` of a configured mirror.
+    fn allows(&self, url: &str) -> bool {
+        if !Url::parse(url).is_ok_and(|url| matches!(url.scheme(), "http" | "https")) {
+            return false;
+        }
+
+        let allow: Vec<&str> = match &self.allow[..] {
+            [] => DEFAULT_FETCH_ALLOW.to_vec(),
+            allow => allow.iter().map(String::as_str).collect()
+        };
+
+        allow.into_iter()
+            .chain(CONFIG.download.mirrors.iter().map(|mirror| mirror.from.as_str()))
+            .any(|prefix| url.starts_with(prefix))
+    }
+}
+
+/// The cache index with its URLs hashed for `/url-hash`.
+#[derive(Default)]
+struct Snapshot {
+    index: CacheIndex,
+    by_url_hash: HashMap<u128, u128>
+}
+
+/// The last loaded [`Snapshot`], reloaded whenever index.cbor changes.
+#[derive(Default)]
+struct Snapshots {
+    modified: Option<SystemTime>,
+    current: Arc<Snapshot>
+}
+
+impl Snapshots {
+    fn current(&mut self) -> Arc<Snapshot> {
+        let modified = CacheIndex::modified();
+
+        if modified.is_none() || modified != self.modified {
+            let index = CacheIndex::load();
+            let by_url_hash = index.entries.iter()
+                .map(|(url, entry)| (MeowHasher::hash(url.as_bytes()).as_u128(), entry.contents))
+                .collect();
+
+            self.current = Arc::new(Snapshot { index, by_url_hash });
+            self.modified = modified;
+        }
+
+        self.current.clone()
+    }
+}
+
+struct State {
+    client: reqwest::Client,
+    fetch: FetchOptions,
+    snapshots: Mutex<Snapshots>
+}
+
+enum Response {
+    Ok(Vec<u8>),
+    Error(StatusCode, String)
+}
+
+pub async fn serve(bind: SocketAddr, fetch: FetchOptions) {
+    let listener = TcpListener::bind(bind).await
+        .unwrap_or_else(|err| panic!("Failed to bind {}: {err}", bind));
+    let client = reqwest::Client::builder()
+        .default_headers(HeaderMap::from_iter([
+            (USER_AGENT, HeaderValue::from_static(USER_AGENT_VALUE))
+        ]))
+        .build()
+        .expect("Failed to build HTTP client");
+
+    if fetch.enabled && !bind.ip().is_loopback() {
+        eprintln!("{}: --fetch on {} lets anyone who can reach it make this machine download from {}", "warning".yellow(), bind,
+            if fetch.allow.is_empty() { DEFAULT_FETCH_ALLOW.join(", ") } else { fetch.allow.join(", ") });
+    }
+
+    let state = Arc::new(State { client, fetch, snapshots: Mutex::default() });
+
+    println!("{:>12} cache on http://{}", "Serving".green(), bind);
+
+    loop {
+        let (stream, peer) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(err) => {
+                eprintln!("{}: failed to accept connection: {}", "warning".yellow(), err);
+                continue;
+            }
+        };
+
+        let state = state.clone();
+        tokio::spawn(async move {
+            if let Err(err) = handle(stream, peer, &state).await {
+                eprintln!("{}: connection from {} failed: {}", "warning".yellow(), peer, err);
+            }
+        });
+    }
+}
+
+async fn handle(mut stream: TcpStream, peer: SocketAddr, state: &State) -> std::io::Result<()> {
+    let mut request = Vec::new();
+    let mut buf = [0u8; 1024];
+
+    while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+        let read = stream.read(&mut buf).await?;
+        if read == 0 || request.len() + read > MAX_REQUEST_SIZE {
+            return Ok(());
+        }
+
+        request.extend_from_slice(&buf[..read]);
+    }
+
+    let request = String::from_utf8_lossy(&request);
+    let mut request_line = request.lines().next().unwrap_or_default().split(' ');
+    let (method, path) = (request_line.next().unwrap_or_default(), request_line.next().unwrap_or_default());
+
+    let response = if method != "GET" {
+        Response::Error(StatusCode::METHOD_NOT_ALLOWED, "only GET is supported".into())
+    } else {
+        route(path, state).await
+    };
+
+    let (status, body) = match response {
+        Response::Ok(body) => (StatusCode::OK, body),
+        Response::Error(status, message) => (status, message.into_bytes())
+    };
+
+    println!("{:>12} {} {} -> {}", "Serve".blue(), peer, path, status);
+
+    stream.write_all(format!(
+        "HTTP/1.1 {}\r\nContent-Length: {}\r\nContent-Type: application/octet-stream\r\nConnection: close\r\n\r\n",
+        status, body.len()
+    ).as_bytes()).await?;
+    stream.write_all(&body).await?;
+    stream.shutdown().await
+}
+
+async fn route(path: &str, state: &State) -> Response {
+    let Ok(url) = Url::parse("http://localhost").and_then(|base| base.join(path)) else {
+        return Response::Error(StatusCode::BAD_REQUEST, "malformed path".into());
+    };
+
+    let segments: Vec<_> = url.path_segments().map(|s| s.collect()).unwrap_or_default();
+    let snapshot = state.snapshots.lock().unwrap_or_else(|err| err.into_inner()).current();
+
+    match segments[..] {
+        ["url-hash", hex] => {
+            let found = u128::from_str_radix(hex, 16).ok()
+                .and_then(|hash| snapshot.by_url_hash.get(&hash));
+
+            match found {
+                Some(contents) => read_contents(*contents).await,
+                None => Response::Error(StatusCode::NOT_FOUND, "URL not cached".into())
+            }
+        },
+
+        ["sha512", hex] => {
+            match snapshot.index.by_sha512.get(&hex.to_ascii_lowercase()) {
+                Some(hash) => read_contents(*hash).await,
+                None => Response::Error(StatusCode::NOT_FOUND, "SHA-512 not cached".into())
+            }
+        },
+
+        ["url"] => {
+            let Some((_, origin)) = url.query_pairs().find(|(key, _)| key == "u") else {
+                return Response::Error(StatusCode::BAD_REQUEST, "missing u parameter".into());
+            };
+
+            if !state.fetch.enabled || !state.fetch.allows(&origin) {
+                return match snapshot.index.entries.get(origin.as_ref()) {
+                    Some(entry) => read_contents(entry.contents).await,
+                    None if state.fetch.enabled => Response::Error(StatusCode::FORBIDDEN, "URL not cached and not allowed to be fetched".into()),
+                    None => Response::Error(StatusCode::NOT_FOUND, "URL not cached".into())
+                };
+            }
+
+            let result = cached::download(&origin, None, || async {
+                let response = state.client.get(CONFIG.mirrored(&origin)).send().await?.error_for_status()?;
+                Ok(response.bytes().await?.into())
+            }).await;
+
+            match result {
+                Ok((_, bytes)) => Response::Ok(bytes),
+                Err(err) => Response::Error(StatusCode::BAD_GATEWAY, format!("failed to fetch {}: {}", origin, err))
+            }
+        },
+
+        _ => Response::Error(StatusCode::NOT_FOUND, "unknown endpoint".into())
+    }
+}
+
+async fn read_contents(hash: u128) -> Response {
+    match tokio::fs::read(cached::contents_path(hash)).await {
+        Ok(bytes) => Response::Ok(bytes),
+        Err(err) => Response::Error(StatusCode::NOT_FOUND, format!("cached contents are missing: {}", err))
+    }
+}
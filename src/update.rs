@@ -0,0 +1,378 @@
+// bumping pinned modrinth versions in jetfuel.xml
+//
+// the file is edited textually, replacing only the values of `version`
+// attributes, so comments and formatting survive
+
+use std::{fs, io::{stdin, stdout, Write}, path::Path, ops::Range};
+
+use colored::Colorize;
+use futures::future::join_all;
+use quick_xml::{Reader, events::{Event, BytesStart}};
+use serde::{Deserialize, de::{IntoDeserializer, value::StrDeserializer}};
+
+use crate::{jp, modrinth::{self, ProjectVersionGetResponse, VersionType}};
+
+const CHANGELOG_LINES: usize = 3;
+const CHANGELOG_WIDTH: usize = 100;
+
+pub struct UpdateOptions {
+    /// Apply every update without asking.
+    pub all: bool,
+    /// Only consider these projects (as written in jetfuel.xml), if not empty.
+    pub only: Vec<String>,
+    pub dry_run: bool
+}
+
+/// A `<modrinth>` element with a pinned version.
+struct PinnedEntry {
+    project: String,
+    version: String,
+    /// Byte range of the version attribute's value in the file.
+    version_span: Range<usize>,
+    game_version: Option<String>,
+    loader: Option<String>,
+    channel: Option<VersionType>
+}
+
+fn attribute(element: &BytesStart, name: &str) -> Option<String> {
+    element.try_get_attribute(name)
+        .unwrap_or_else(|err| panic!("Malformed attribute in jetfuel.xml: {err}"))
+        .map(|attr| attr.unescape_value()
+            .unwrap_or_else(|err| panic!("Malformed attribute in jetfuel.xml: {err}"))
+            .into_owned())
+}
+
+/// Finds where the value of the `name` attribute is in `tag` (`<tag ...>`).
+fn attribute_value_span(tag: &str, name: &str) -> Option<Range<usize>> {
+    let mut search_from = 0;
+
+    while let Some(found) = tag[search_from..].find(name) {
+        let start = search_from + found;
+        search_from = start + name.len();
+
+        // `version` must not match the end of `game-version`
+        if !tag[..start].ends_with(|c: char| c.is_ascii_whitespace()) {
+            continue;
+        }
+
+        let rest = tag[search_from..].trim_start();
+        let Some(rest) = rest.strip_prefix('=') else {
+            continue;
+        };
+        let rest = rest.trim_start();
+        let Some(quote) = rest.chars().next().filter(|c| *c == '"' || *c == '\'') else {
+            continue;
+        };
+
+        let value_start = tag.len() - rest.len() + 1;
+        let value_len = tag[value_start..].find(quote)?;
+        return Some(value_start..value_start + value_len);
+    }
+
+    None
+}
+
+fn find_entries(text: &str) -> (Vec<PinnedEntry>, Option<String>) {
+    let mut reader = Reader::from_str(text);
+    let mut entries = vec![];
+    let mut fabric_minecraft = None;
+
+    loop {
+        let tag_start = reader.buffer_position();
+        let event = reader.read_event()
+            .unwrap_or_else(|err| panic!("Failed to read jetfuel.xml at byte {}: {err}", reader.buffer_position()));
+
+        let element = match &event {
+            Event::Start(element) | Event::Empty(element) => element,
+            Event::Eof => break,
+            _ => continue
+        };
+
+        match element.name().as_ref() {
+            b"fabric-server" => {
+                fabric_minecraft = fabric_minecraft.or_else(|| attribute(element, "minecraft"));
+            },
+            b"modrinth" => {
+                let (Some(project), Some(version)) = (attribute(element, "project"), attribute(element, "version")) else {
+                    continue; // not pinned, so nothing to update
+                };
+
+                let tag = &text[tag_start..reader.buffer_position()];
+                let span = attribute_value_span(tag, "version")
+                    .unwrap_or_else(|| panic!("Could not find the version of {} in jetfuel.xml", project));
+
+                entries.push(PinnedEntry {
+                    project,
+                    version,
+                    version_span: tag_start + span.start..tag_start + span.end,
+                    game_version: attribute(element, "game-version"),
+                    loader: attribute(element, "loader"),
+                    channel: attribute(element, "channel").map(|channel| parse_channel(&channel))
+                });
+            },
+            _ => {}
+        }
+    }
+
+    (entries, fabric_minecraft)
+}
+
+/// Reads a `channel` attribute the same way packing does.
+fn parse_channel(channel: &str) -> VersionType {
+    let deserializer: StrDeserializer<'_, serde::de::value::Error> = channel.into_deserializer();
+    VersionType::deserialize(deserializer)
+        .unwrap_or_else(|err| panic!("Unknown Modrinth channel {}: {err}", channel))
+}
+
+/// Writes each new version over the span of the old one in `text`.
+fn apply_replacements(text: &mut String, replacements: &mut [(Range<usize>, String)]) {
+    // from the end, so earlier spans stay valid
+    replacements.sort_by_key(|(span, _)| std::cmp::Reverse(span.start));
+    for (span, new_version) in replacements.iter() {
+        text.replace_range(span.clone(), &quick_xml::escape::escape(new_version));
+    }
+}
+
+fn is_pinned(entry: &PinnedEntry, version: &ProjectVersionGetResponse) -> bool {
+    entry.version == version.id || entry.version == version.version_number
+}
+
+/// Whether `version` was published after the pinned version, so that offering
+/// it is not a downgrade.
+fn is_newer(version: &ProjectVersionGetResponse, pinned_published: &str) -> bool {
+    // RFC 3339 timestamps, as compared by `modrinth::newest_version`
+    version.date_published.as_str() > pinned_published
+}
+
+fn print_changelog(version: &ProjectVersionGetResponse) {
+    let Some(changelog) = &version.changelog else {
+        return;
+    };
+
+    let lines: Vec<_> = changelog.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .collect();
+
+    for line in lines.iter().take(CHANGELOG_LINES) {
+        let line = match line.char_indices().nth(CHANGELOG_WIDTH) {
+            Some((end, _)) => format!("{}...", &line[..end]),
+            None => line.to_string()
+        };
+
+        println!("{:>12} {}", "|".dimmed(), line);
+    }
+
+    if lines.len() > CHANGELOG_LINES {
+        println!("{:>12} {}", "|".dimmed(), "(more in the full changelog)".dimmed());
+    }
+}
+
+fn confirm(project: &str) -> bool {
+    print!("Update {}? [y/N] -> ", project);
+    let _ = stdout().flush();
+
+    let mut answer = String::new();
+    stdin().read_line(&mut answer).expect("Failed to read answer");
+    matches!(answer.trim(), "y" | "Y")
+}
+
+pub async fn update<P : AsRef<Path>>(jetfuel_path: P, options: UpdateOptions) {
+    let jetfuel_path = jetfuel_path.as_ref();
+    let mut text = fs::read_to_string(jetfuel_path)
+        .unwrap_or_else(|err| panic!("Failed to read {:?}: {err}", jetfuel_path));
+
+    let (entries, fabric_minecraft) = find_entries(&text);
+    let entries: Vec<_> = entries.into_iter()
+        .filter(|entry| options.only.is_empty() || options.only.contains(&entry.project))
+        .collect();
+
+    for only in &options.only {
+        if !entries.iter().any(|entry| &entry.project == only) {
+            eprintln!("{}: no Modrinth entry with a pinned version for {}", "warning".yellow(), only);
+        }
+    }
+
+    let client = jp::http_client();
+    let newest = join_all(entries.iter().map(|entry| {
+        let client = &client;
+        let fabric_minecraft = &fabric_minecraft;
+
+        async move {
+            let game_version = entry.game_version.as_ref().or(fabric_minecraft.as_ref())
+                .unwrap_or_else(|| panic!("{} needs a game-version, or the pack a <fabric-server>, to find updates", entry.project));
+            // same default as packing: the pack's <fabric-server> means fabric
+            let loader = entry.loader.clone()
+                .or_else(|| fabric_minecraft.as_ref().map(|_| "fabric".into()))
+                .unwrap_or_else(|| panic!("{} needs a loader, or the pack a <fabric-server>, to find updates", entry.project));
+
+            let versions = modrinth::project_versions_get(client, &entry.project, game_version, &loader).await;
+
+            // the pinned version may be outside the channel or this game version
+            let pinned_published = match versions.iter().find(|version| is_pinned(entry, version)) {
+                Some(pinned) => pinned.date_published.clone(),
+                None => modrinth::project_version_get(client, &entry.project, &entry.version).await.date_published
+            };
+
+            (modrinth::newest_version(versions, entry.channel.unwrap_or(VersionType::Release)), pinned_published)
+        }
+    })).await;
+
+    let mut replacements = vec![];
+
+    for (entry, (newest, pinned_published)) in entries.iter().zip(newest) {
+        let Some(newest) = newest else {
+            eprintln!("{}: {} has no compatible version", "warning".yellow(), entry.project);
+            continue;
+        };
+
+        if is_pinned(entry, &newest) {
+            println!("{:>12} {} {}", "Up to date".green(), entry.project, entry.version);
+            continue;
+        }
+
+        if !is_newer(&newest, &pinned_published) {
+            println!("{:>12} {} {} (newer than {})", "Up to date".green(), entry.project, entry.version, newest.version_number);
+            continue;
+        }
+
+        // keep pinning the way the entry already does
+        let new_version = if modrinth::is_version_id(&entry.version) {
+            newest.id.clone()
+        } else {
+            newest.version_number.clone()
+        };
+
+        println!("{:>12} {} {} -> {}", "Update".yellow(), entry.project, entry.version, new_version);
+        print_changelog(&newest);
+
+        if options.dry_run || options.all || confirm(&entry.project) {
+            replacements.push((entry.version_span.clone(), new_version));
+        }
+    }
+
+    if options.dry_run {
+        println!("{:>12} {} update(s) (dry run; {} not changed)", "Found".blue(), replacements.len(), jetfuel_path.to_str().unwrap());
+        return;
+    }
+
+    if replacements.is_empty() {
+        return;
+    }
+
+    apply_replacements(&mut text, &mut replacements);
+
+    fs::write(jetfuel_path, text)
+        .unwrap_or_else(|err| panic!("Failed to write {:?}: {err}", jetfuel_path));
+    println!("{:>12} {} Modrinth version(s) in {}", "Updated".green(), replacements.len(), jetfuel_path.to_str().unwrap());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Pinned versions found in `text`, as written in the file.
+    fn spans(text: &str) -> Vec<&str> {
+        find_entries(text).0.iter()
+            .map(|entry| &text[entry.version_span.clone()])
+            .collect()
+    }
+
+    #[test]
+    fn game_version_before_version() {
+        let text = r#"<jetfuel><modrinth project="a" game-version="1.20.1" version="1.0"/></jetfuel>"#;
+        assert_eq!(spans(text), ["1.0"]);
+    }
+
+    #[test]
+    fn single_quotes() {
+        let text = "<jetfuel><modrinth project='a' version='1.0'/></jetfuel>";
+        assert_eq!(spans(text), ["1.0"]);
+    }
+
+    #[test]
+    fn whitespace_around_equals() {
+        let text = r#"<jetfuel><modrinth project="a" version = "1.0"/></jetfuel>"#;
+        assert_eq!(spans(text), ["1.0"]);
+    }
+
+    #[test]
+    fn attribute_split_across_lines() {
+        let text = "<jetfuel>\n    <modrinth project=\"a\"\n        version\n        =\n        \"1.0\"/>\n</jetfuel>";
+        assert_eq!(spans(text), ["1.0"]);
+    }
+
+    #[test]
+    fn several_entries_on_one_line() {
+        let text = r#"<jetfuel><modrinth project="a" version="1.0"/><modrinth project="b" version="2.0"/></jetfuel>"#;
+        let (entries, _) = find_entries(text);
+
+        assert_eq!(spans(text), ["1.0", "2.0"]);
+        assert_eq!(entries[1].project, "b");
+
+        let mut text = text.to_string();
+        let mut replacements: Vec<_> = entries.iter()
+            .map(|entry| (entry.version_span.clone(), format!("{}.1", entry.version)))
+            .collect();
+        apply_replacements(&mut text, &mut replacements);
+
+        assert_eq!(text, r#"<jetfuel><modrinth project="a" version="1.0.1"/><modrinth project="b" version="2.0.1"/></jetfuel>"#);
+    }
+
+    #[test]
+    fn value_needing_escaping() {
+        let text = r#"<jetfuel><modrinth project="a" version="1.0&amp;beta"/></jetfuel>"#;
+        let (entries, _) = find_entries(text);
+
+        assert_eq!(entries[0].version, "1.0&beta");
+        assert_eq!(spans(text), ["1.0&amp;beta"]);
+
+        let mut text = text.to_string();
+        apply_replacements(&mut text, &mut [(entries[0].version_span.clone(), "2.0<\"rc\">".to_string())]);
+
+        assert_eq!(text, r#"<jetfuel><modrinth project="a" version="2.0&lt;&quot;rc&quot;&gt;"/></jetfuel>"#);
+        assert_eq!(find_entries(&text).0[0].version, "2.0<\"rc\">");
+    }
+
+    fn version(version_number: &str, date_published: &str) -> ProjectVersionGetResponse {
+        serde_json::from_value(serde_json::json!({
+            "name": version_number,
+            "version_number": version_number,
+            "game_versions": ["1.20.1"],
+            "version_type": "release",
+            "loaders": ["fabric"],
+            "featured": false,
+            "id": "AAAAAAAA",
+            "project_id": "BBBBBBBB",
+            "author_id": "CCCCCCCC",
+            "date_published": date_published,
+            "downloads": 0,
+            "files": []
+        })).unwrap()
+    }
+
+    #[test]
+    fn newer_release_is_offered() {
+        assert!(is_newer(&version("1.1", "2023-11-05T12:00:00Z"), "2023-10-01T12:00:00Z"));
+    }
+
+    #[test]
+    fn newer_pinned_version_is_left_alone() {
+        // e.g. a beta pinned past the newest release
+        assert!(!is_newer(&version("1.0", "2023-10-01T12:00:00Z"), "2023-11-05T12:00:00Z"));
+        assert!(!is_newer(&version("1.0", "2023-10-01T12:00:00Z"), "2023-10-01T12:00:00Z"));
+    }
+
+    #[test]
+    fn channels() {
+        assert_eq!(parse_channel("release"), VersionType::Release);
+        assert_eq!(parse_channel("beta"), VersionType::Beta);
+        assert_eq!(parse_channel("alpha"), VersionType::Alpha);
+    }
+
+    #[test]
+    #[should_panic(expected = "Unknown Modrinth channel")]
+    fn unknown_channel() {
+        parse_channel("nightly");
+    }
+}